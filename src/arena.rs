@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use crate::expr::*;
+
+// The index of a node in an `Arena`. Nodes are hash-consed, so two ids from the
+// same arena are equal exactly when the trees they refer to are equal
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Node {
+    Leaf,
+    Stem(NodeId),
+    Fork(NodeId, NodeId),
+    // An application whose function is a fork or another application. Leaves
+    // and stems applied to something are stems and forks instead
+    App(NodeId, NodeId),
+}
+
+// Storage for trees where every distinct subtree is stored only once
+#[derive(Default)]
+pub struct Arena {
+    nodes: Vec<Node>,
+    ids: HashMap<Node, NodeId>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self {nodes: Vec::new(), ids: HashMap::new()}
+    }
+
+    // Return the number of distinct nodes
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn node(&self, id: NodeId) -> Node {
+        self.nodes[id.0 as usize]
+    }
+
+    fn intern(&mut self, node: Node) -> NodeId {
+        if let Some(id) = self.ids.get(&node) {
+            return *id;
+        }
+        let id = NodeId(self.nodes.len() as u32);
+        self.nodes.push(node);
+        self.ids.insert(node, id);
+        id
+    }
+
+    pub fn leaf(&mut self) -> NodeId {
+        self.intern(Node::Leaf)
+    }
+
+    pub fn stem(&mut self, child: NodeId) -> NodeId {
+        self.intern(Node::Stem(child))
+    }
+
+    pub fn fork(&mut self, left: NodeId, right: NodeId) -> NodeId {
+        self.intern(Node::Fork(left, right))
+    }
+
+    // Apply `f` to `x` without evaluating anything, like adding `x` as the last
+    // child of an `Expr`
+    pub fn app(&mut self, f: NodeId, x: NodeId) -> NodeId {
+        match self.node(f) {
+            Node::Leaf => self.stem(x),
+            Node::Stem(a) => self.fork(a, x),
+            Node::Fork(..) | Node::App(..) => self.intern(Node::App(f, x)),
+        }
+    }
+
+    pub fn intern_expr(&mut self, expr: &Expr) -> NodeId {
+        enum Task<'a> {
+            // Intern the node made of the first `len` children of `expr`
+            Visit(&'a Expr, usize),
+            // Pop the children of a node with `len` children from the results
+            // and intern the node
+            Build(usize),
+        }
+        let mut tasks = vec![Task::Visit(expr, expr.children.len())];
+        let mut results = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(expr, len) => {
+                    tasks.push(Task::Build(len));
+                    if len <= 2 {
+                        tasks.extend(expr.children[..len].iter().rev().map(|c| Task::Visit(c, c.children.len())));
+                    } else {
+                        let last_child = &expr.children[len - 1];
+                        tasks.push(Task::Visit(last_child, last_child.children.len()));
+                        tasks.push(Task::Visit(expr, len - 1));
+                    }
+                }
+                Task::Build(len) => {
+                    let id = match len {
+                        0 => self.leaf(),
+                        1 => {
+                            let child = results.pop().unwrap();
+                            self.stem(child)
+                        }
+                        _ => {
+                            let right = results.pop().unwrap();
+                            let left = results.pop().unwrap();
+                            if len == 2 {
+                                self.fork(left, right)
+                            } else {
+                                self.app(left, right)
+                            }
+                        }
+                    };
+                    results.push(id);
+                }
+            }
+        }
+        results.pop().unwrap()
+    }
+
+    // Expand the node into an `Expr`. Shared subtrees are copied wherever they
+    // occur
+    pub fn to_expr(&self, id: NodeId) -> Expr {
+        enum Task {
+            Visit(NodeId),
+            // Pop this many trees from the results and make them the children
+            // of a new node
+            Build(usize),
+            // Pop a tree from the results and add it as the last child of the
+            // tree below it
+            Append,
+        }
+        let mut tasks = vec![Task::Visit(id)];
+        let mut results: Vec<Expr> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(id) => match self.node(id) {
+                    Node::Leaf => results.push(Expr::leaf()),
+                    Node::Stem(child) => tasks.extend([Task::Build(1), Task::Visit(child)]),
+                    Node::Fork(left, right) => {
+                        tasks.extend([Task::Build(2), Task::Visit(right), Task::Visit(left)]);
+                    }
+                    Node::App(f, x) => tasks.extend([Task::Append, Task::Visit(x), Task::Visit(f)]),
+                },
+                Task::Build(n) => {
+                    let children = results.split_off(results.len() - n);
+                    results.push(Expr::new(children));
+                }
+                Task::Append => {
+                    let x = results.pop().unwrap();
+                    results.last_mut().unwrap().children.push(x);
+                }
+            }
+        }
+        results.pop().unwrap()
+    }
+}
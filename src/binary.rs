@@ -0,0 +1,202 @@
+// A compact binary format for trees, which stores every distinct subtree once:
+// - the magic bytes `TCDAG`, then the version of the format as one byte
+// - the number of nodes, as a varint
+// - the nodes, every node after its children. A node is a tag byte (0 = leaf,
+//   1 = stem, 2 = fork, 3 = application) followed by its children, each as a
+//   varint back-reference: 1 is the node just before it, 2 the one before that,
+//   and so on
+// - the root is the last node
+//
+// Varints are LEB128: 7 bits at a time, least significant first, with the high
+// bit set on every byte but the last one
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::arena::*;
+use crate::expr::*;
+
+pub const MAGIC: &[u8; 5] = b"TCDAG";
+pub const VERSION: u8 = 1;
+
+const TAG_LEAF: u8 = 0;
+const TAG_STEM: u8 = 1;
+const TAG_FORK: u8 = 2;
+const TAG_APP: u8 = 3;
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    Corrupt(String),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(err) => write!(f, "cannot read tree: {}", err),
+            ReadError::BadMagic => write!(f, "not a binary tree file"),
+            ReadError::UnsupportedVersion(version) => {
+                write!(f, "unsupported binary tree format version {} (expected {})", version, VERSION)
+            }
+            ReadError::Corrupt(msg) => write!(f, "corrupt binary tree file: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            ReadError::Corrupt("unexpected end of file".to_string())
+        } else {
+            ReadError::Io(err)
+        }
+    }
+}
+
+fn write_varint(w: &mut impl Write, mut n: u64) -> io::Result<()> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_byte(r: &mut impl Read) -> Result<u8, ReadError> {
+    let mut byte = [0];
+    r.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_varint(r: &mut impl Read) -> Result<u64, ReadError> {
+    let mut n: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = read_byte(r)?;
+        let bits = (byte & 0x7f) as u64;
+        if shift == 63 && bits > 1 {
+            break;
+        }
+        n |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(ReadError::Corrupt("varint does not fit in 64 bits".to_string()))
+}
+
+// Return the children of a node, in the order they are written
+fn node_children(node: Node) -> Vec<NodeId> {
+    match node {
+        Node::Leaf => vec![],
+        Node::Stem(child) => vec![child],
+        Node::Fork(left, right) | Node::App(left, right) => vec![left, right],
+    }
+}
+
+// Write the tree at `root` and every node it refers to. Nodes of the arena that
+// `root` does not use are left out
+pub fn write_node(arena: &Arena, root: NodeId, w: &mut impl Write) -> io::Result<()> {
+    // Order the nodes so that every node comes after its children
+    let mut order = Vec::new();
+    let mut indices: HashMap<NodeId, u64> = HashMap::new();
+    let mut stack = vec![(root, false)];
+    while let Some((id, children_done)) = stack.pop() {
+        if indices.contains_key(&id) {
+            continue;
+        }
+        if children_done {
+            indices.insert(id, order.len() as u64);
+            order.push(id);
+        } else {
+            stack.push((id, true));
+            let children = node_children(arena.node(id));
+            stack.extend(children.into_iter().rev().filter(|c| !indices.contains_key(c)).map(|c| (c, false)));
+        }
+    }
+
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION])?;
+    write_varint(w, order.len() as u64)?;
+    for (index, id) in order.iter().enumerate() {
+        let node = arena.node(*id);
+        let tag = match node {
+            Node::Leaf => TAG_LEAF,
+            Node::Stem(_) => TAG_STEM,
+            Node::Fork(..) => TAG_FORK,
+            Node::App(..) => TAG_APP,
+        };
+        w.write_all(&[tag])?;
+        for child in node_children(node) {
+            write_varint(w, index as u64 - indices[&child])?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_expr(expr: &Expr, w: &mut impl Write) -> io::Result<()> {
+    let mut arena = Arena::new();
+    let root = arena.intern_expr(expr);
+    write_node(&arena, root, w)
+}
+
+// Read a tree into `arena` and return its root
+pub fn read_node(arena: &mut Arena, r: &mut impl Read) -> Result<NodeId, ReadError> {
+    let mut magic = [0; MAGIC.len()];
+    r.read_exact(&mut magic).map_err(|_| ReadError::BadMagic)?;
+    if magic != *MAGIC {
+        return Err(ReadError::BadMagic);
+    }
+    let version = read_byte(r)?;
+    if version != VERSION {
+        return Err(ReadError::UnsupportedVersion(version));
+    }
+    let count = read_varint(r)?;
+    if count == 0 {
+        return Err(ReadError::Corrupt("no nodes".to_string()));
+    }
+
+    // The arena id of every node read so far. The count comes from the file,
+    // so it is not trusted for the initial capacity
+    let mut ids: Vec<NodeId> = Vec::with_capacity(count.min(1 << 20) as usize);
+    for index in 0..count {
+        let child = |r: &mut _| -> Result<NodeId, ReadError> {
+            let back = read_varint(r)?;
+            if back == 0 || back > index {
+                return Err(ReadError::Corrupt(format!("node {} refers to a node that is not before it", index)));
+            }
+            Ok(ids[(index - back) as usize])
+        };
+        let id = match read_byte(r)? {
+            TAG_LEAF => arena.leaf(),
+            TAG_STEM => {
+                let child = child(r)?;
+                arena.stem(child)
+            }
+            tag @ (TAG_FORK | TAG_APP) => {
+                let left = child(r)?;
+                let right = child(r)?;
+                if tag == TAG_FORK {
+                    arena.fork(left, right)
+                } else {
+                    arena.app(left, right)
+                }
+            }
+            tag => return Err(ReadError::Corrupt(format!("unknown node tag {}", tag))),
+        };
+        ids.push(id);
+    }
+    Ok(*ids.last().unwrap())
+}
+
+pub fn read_expr(r: &mut impl Read) -> Result<Expr, ReadError> {
+    let mut arena = Arena::new();
+    let root = read_node(&mut arena, r)?;
+    Ok(arena.to_expr(root))
+}
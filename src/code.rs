@@ -1,3 +1,4 @@
+use std::fmt;
 use std::ops::Index;
 
 use crate::agent::*;
@@ -6,6 +7,7 @@ use crate::global::*;
 
 pub struct Code(Vec<Instr>);
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ConnectMode {NoRef, LeftRef, RightRef, FullRef}
 
@@ -52,77 +54,105 @@ impl Code {
 
     // Given a heap address and an expr, compile the creation of the expr (as a
     // chain of L's and A's) at the given address to instructions, and return
-    // the next free heap address
+    // the next free heap address.
+    //
+    // Agents are allocated in prefix order, so the node at `heap_addr` is
+    // followed by the agents of its first child, then by those of its second
+    // child. A node with more than two children is an application of the node
+    // made of all but its last child to the last child. Deep trees would
+    // overflow the call stack, so the traversal uses its own stack of tasks
     fn expr_to_code(&mut self, expr: &Expr, heap_addr: HeapAddress) -> HeapAddress {
-        match expr.children.len() {
-            0 => {
-                self.record_instrs(&[Instr::MkAgent(0, AgentType::L)]);
-                heap_addr + 1
-            },
-            1 => {
-                self.record_instrs(&[Instr::MkAgent(0, AgentType::S)]);
-                let heap_addr_1 = heap_addr + 1;
-                let heap_addr_2 = self.expr_to_code(&expr.children[0], heap_addr_1);
-                self.record_instrs(&[
-                    Instr::Load(0, heap_addr),
-                    Instr::Load(1, heap_addr_1),
-                ]);
-                if expr.children[0].children.len() < 3 {
-                    self.record_instrs(&[Instr::Connect(0, PortNum::P0, 1, PortNum::Main, ConnectMode::NoRef)]);
-                } else {
-                    self.record_instrs(&[Instr::Connect(0, PortNum::P0, 1, PortNum::P1, ConnectMode::NoRef)]);
-                }
-                heap_addr_2
-            },
-            2 => {
-                self.record_instrs(&[Instr::MkAgent(0, AgentType::F)]);
-                let heap_addr_1 = heap_addr + 1;
-                let heap_addr_2 = self.expr_to_code(&expr.children[0], heap_addr_1);
-                let heap_addr_3 = self.expr_to_code(&expr.children[1], heap_addr_2);
-                self.record_instrs(&[
-                    Instr::Load(0, heap_addr),
-                    Instr::Load(1, heap_addr_1),
-                    Instr::Load(2, heap_addr_2),
-                ]);
-                if expr.children[0].children.len() < 3 {
-                    self.record_instrs(&[Instr::Connect(0, PortNum::P0, 1, PortNum::Main, ConnectMode::NoRef)]);
-                } else {
-                    self.record_instrs(&[Instr::Connect(0, PortNum::P0, 1, PortNum::P1, ConnectMode::NoRef)]);
-                }
-                if expr.children[1].children.len() < 3 {
-                    self.record_instrs(&[Instr::Connect(0, PortNum::P1, 2, PortNum::Main, ConnectMode::NoRef)]);
-                } else {
-                    self.record_instrs(&[Instr::Connect(0, PortNum::P1, 2, PortNum::P1, ConnectMode::NoRef)]);
-                }
-                heap_addr_3
-            }
-            _ => {
-                // println!("\nn children");
-                println!("{} children found", expr.children.len());
-                self.record_instrs(&[Instr::MkAgent(0, AgentType::A)]);
-                let heap_addr_1 = heap_addr + 1;
-                let (last_child, rest) = expr.children.split_last().unwrap();
-                let new_expr = Expr{children: rest.to_vec()};
-                let heap_addr_2 = self.expr_to_code(&new_expr, heap_addr_1);
-                let heap_addr_3 = self.expr_to_code(last_child, heap_addr_2);
-                self.record_instrs(&[
-                    Instr::Load(0, heap_addr), // A
-                    Instr::Load(1, heap_addr_1), // left
-                    Instr::Load(2, heap_addr_2), // right
-                ]);
-                if new_expr.children.len() < 3 {
-                    self.record_instrs(&[Instr::Connect(1, PortNum::Main, 0, PortNum::Main, ConnectMode::NoRef)]);
-                } else {
-                    self.record_instrs(&[Instr::Connect(0, PortNum::Main, 1, PortNum::P1, ConnectMode::NoRef)]);
+        enum Task<'a> {
+            // Create the node made of the first `len` children of `expr`, and
+            // store its address in `addrs[slot]`
+            Visit {expr: &'a Expr, len: usize, slot: usize},
+            // Connect the node at `addr` to its children, whose addresses are
+            // in `addrs[slot]` and `addrs[slot + 1]`
+            Finish {expr: &'a Expr, len: usize, addr: HeapAddress, slot: usize},
+        }
+
+        // The port of a node that its parent connects to
+        fn port_of(len: usize) -> PortNum {
+            if len < 3 {PortNum::Main} else {PortNum::P1}
+        }
+
+        let mut next_addr = heap_addr;
+        let mut addrs = vec![UNASSIGNED_PORT];
+        let mut tasks = vec![Task::Visit {expr, len: expr.children.len(), slot: 0}];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit {expr, len, slot} => {
+                    let agent_type = match len {
+                        0 => AgentType::L,
+                        1 => AgentType::S,
+                        2 => AgentType::F,
+                        _ => {
+                            crate::debug_log!("{} children found", len);
+                            AgentType::A
+                        }
+                    };
+                    self.record_instrs(&[Instr::MkAgent(0, agent_type)]);
+                    addrs[slot] = next_addr;
+                    let child_slot = addrs.len();
+                    addrs.extend([UNASSIGNED_PORT, UNASSIGNED_PORT]);
+                    tasks.push(Task::Finish {expr, len, addr: next_addr, slot: child_slot});
+                    next_addr += 1;
+
+                    // The children are pushed in reverse, so the first one is
+                    // visited first
+                    match len {
+                        0 => {}
+                        1 | 2 => {
+                            for i in (0..len).rev() {
+                                let child = &expr.children[i];
+                                tasks.push(Task::Visit {expr: child, len: child.children.len(), slot: child_slot + i});
+                            }
+                        }
+                        _ => {
+                            let last_child = &expr.children[len - 1];
+                            tasks.push(Task::Visit {expr: last_child, len: last_child.children.len(), slot: child_slot + 1});
+                            tasks.push(Task::Visit {expr, len: len - 1, slot: child_slot});
+                        }
+                    }
                 }
-                if last_child.children.len() < 3 {
-                    self.record_instrs(&[Instr::Connect(0, PortNum::P0, 2, PortNum::Main, ConnectMode::NoRef)]);
-                } else {
-                    self.record_instrs(&[Instr::Connect(0, PortNum::P0, 2, PortNum::P1, ConnectMode::NoRef)]);
+                Task::Finish {expr, len, addr, slot} => {
+                    match len {
+                        0 => {}
+                        1 => {
+                            self.record_instrs(&[
+                                Instr::Load(0, addr),
+                                Instr::Load(1, addrs[slot]),
+                                Instr::Connect(0, PortNum::P0, 1, port_of(expr.children[0].children.len()), ConnectMode::NoRef),
+                            ]);
+                        }
+                        2 => {
+                            self.record_instrs(&[
+                                Instr::Load(0, addr),
+                                Instr::Load(1, addrs[slot]),
+                                Instr::Load(2, addrs[slot + 1]),
+                                Instr::Connect(0, PortNum::P0, 1, port_of(expr.children[0].children.len()), ConnectMode::NoRef),
+                                Instr::Connect(0, PortNum::P1, 2, port_of(expr.children[1].children.len()), ConnectMode::NoRef),
+                            ]);
+                        }
+                        _ => {
+                            self.record_instrs(&[
+                                Instr::Load(0, addr), // A
+                                Instr::Load(1, addrs[slot]), // left
+                                Instr::Load(2, addrs[slot + 1]), // right
+                            ]);
+                            if len - 1 < 3 {
+                                self.record_instrs(&[Instr::Connect(1, PortNum::Main, 0, PortNum::Main, ConnectMode::NoRef)]);
+                            } else {
+                                self.record_instrs(&[Instr::Connect(0, PortNum::Main, 1, PortNum::P1, ConnectMode::NoRef)]);
+                            }
+                            let last_child = &expr.children[len - 1];
+                            self.record_instrs(&[Instr::Connect(0, PortNum::P0, 2, port_of(last_child.children.len()), ConnectMode::NoRef)]);
+                        }
+                    }
                 }
-                heap_addr_3
             }
         }
+        next_addr
     }
 
     pub fn from_instrs(instrs: &[Instr]) -> Self {
@@ -136,7 +166,7 @@ impl Code {
         let mut code = Self::from_instrs(&[Instr::MkAgent(0, AgentType::I)]);
 
         // Compile the expr into code
-        code.expr_to_code(&expr, 1);
+        code.expr_to_code(expr, 1);
 
         code.record_instrs(&[
             Instr::Load(0, 0),
@@ -158,6 +188,87 @@ impl Code {
     }
 }
 
+// A tree of an open net: `expr` applied to the holes named in `holes`, in
+// order, with its result connected to a free port named `name`
+pub struct OpenTerm<'a> {
+    pub name: &'a str,
+    pub expr: &'a Expr,
+    pub holes: &'a [&'a str],
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OpenNetError {
+    // Two free ports, results or holes, have this name
+    DuplicateName(String),
+}
+
+impl fmt::Display for OpenNetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenNetError::DuplicateName(name) => write!(f, "free port {} is used twice", name),
+        }
+    }
+}
+
+impl Code {
+    // Compile several trees into one net, so they are evaluated together. Every
+    // result and every hole is a free port, made of an interface agent. Return
+    // the code, and the heap address of the interface of each free port, by
+    // name, in the order they are created. A hole is a port that a tree is
+    // later connected to, and can only be used once. Each term is compiled on
+    // its own, so work is shared between terms only through the evaluation
+    pub fn from_open_net(terms: &[OpenTerm]) -> Result<(Self, Vec<(String, HeapAddress)>), OpenNetError> {
+        // Every free port needs its own name
+        let mut names: Vec<&str> = Vec::new();
+        for term in terms {
+            for &name in std::iter::once(&term.name).chain(term.holes) {
+                if names.contains(&name) {
+                    return Err(OpenNetError::DuplicateName(name.to_string()));
+                }
+                names.push(name);
+            }
+        }
+
+        let mut code = Self(Vec::new());
+        let mut ports: Vec<(String, HeapAddress)> = Vec::new();
+        let mut next_addr = 0;
+        for term in terms {
+            let interface = next_addr;
+            code.record_instrs(&[Instr::MkAgent(0, AgentType::I)]);
+            ports.push((term.name.to_string(), interface));
+            let root = next_addr + 1;
+            next_addr = code.expr_to_code(term.expr, root);
+
+            // The port the result of the term so far comes out of
+            let mut result = (root, if term.expr.children.len() < 3 {PortNum::Main} else {PortNum::P1});
+            for hole in term.holes {
+                let app = next_addr;
+                let hole_interface = next_addr + 1;
+                next_addr += 2;
+                code.record_instrs(&[
+                    Instr::MkAgent(0, AgentType::A),
+                    Instr::MkAgent(0, AgentType::I),
+                    Instr::Load(0, app),
+                    Instr::Load(1, result.0),
+                    Instr::Load(2, hole_interface),
+                    Instr::Connect(1, result.1, 0, PortNum::Main, ConnectMode::NoRef),
+                    Instr::Connect(0, PortNum::P0, 2, PortNum::P0, ConnectMode::NoRef),
+                ]);
+                ports.push((hole.to_string(), hole_interface));
+                result = (app, PortNum::P1);
+            }
+
+            code.record_instrs(&[
+                Instr::Load(0, interface),
+                Instr::Load(1, result.0),
+                Instr::Connect(1, result.1, 0, PortNum::P0, ConnectMode::NoRef),
+            ]);
+        }
+        code.record_instrs(&[Instr::Return]);
+        Ok((code, ports))
+    }
+}
+
 impl Index<usize> for Code {
     type Output = Instr;
 
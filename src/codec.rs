@@ -0,0 +1,323 @@
+// Conversions between trees and ordinary data, using the usual tree calculus
+// encodings (the same ones as the literals of the source language):
+// - false = t, true = t t
+// - a natural number is the list of its binary digits, least significant
+//   first, so 0 = t and 6 = [false, true, true]
+// - an integer is the pair of its sign (true if negative) and its magnitude
+// - a list is a chain of forks ending in a leaf: [a, b] = t a (t b t)
+// - a byte string is the list of its bytes, a string is the byte string of its
+//   UTF-8 encoding
+// - a pair is a fork: (a, b) = t a b
+// - none = t, some x = t x
+
+use std::fmt;
+
+use crate::expr::*;
+
+pub fn encode_bool(b: bool) -> Expr {
+    if b {
+        Expr::stem(Expr::leaf())
+    } else {
+        Expr::leaf()
+    }
+}
+
+pub fn decode_bool(expr: &Expr) -> Option<bool> {
+    match expr.children.len() {
+        0 => Some(false),
+        1 if expr.children[0].children.is_empty() => Some(true),
+        _ => None,
+    }
+}
+
+// Encode a list of binary digits, least significant first
+fn encode_bits(bits: impl Iterator<Item = bool>) -> Expr {
+    encode_list(bits.map(encode_bool).collect())
+}
+
+pub fn encode_nat(n: u64) -> Expr {
+    let bit_count = 64 - n.leading_zeros();
+    encode_bits((0..bit_count).map(|i| n >> i & 1 == 1))
+}
+
+// Encode a natural number of any size, given by its decimal digits
+pub fn encode_nat_decimal(digits: &str) -> Option<Expr> {
+    if digits.is_empty() || !digits.bytes().all(|d| d.is_ascii_digit()) {
+        return None;
+    }
+    let mut decimal: Vec<u8> = digits.bytes().map(|d| d - b'0').collect();
+    let mut bits = Vec::new();
+    while decimal.iter().any(|d| *d != 0) {
+        // Divide the decimal number by two, keeping the remainder
+        let mut carry = 0;
+        for d in decimal.iter_mut() {
+            let value = carry * 10 + *d;
+            *d = value / 2;
+            carry = value % 2;
+        }
+        bits.push(carry == 1);
+    }
+    Some(encode_bits(bits.into_iter()))
+}
+
+// Return the binary digits of a natural number, least significant first
+fn decode_bits(expr: &Expr) -> Option<Vec<bool>> {
+    decode_list(expr)?.into_iter().map(decode_bool).collect()
+}
+
+// Fails if the number does not fit in a u64
+pub fn decode_nat(expr: &Expr) -> Option<u64> {
+    let bits = decode_bits(expr)?;
+    let mut n: u64 = 0;
+    for (i, bit) in bits.into_iter().enumerate() {
+        if bit {
+            n = n.checked_add(1u64.checked_shl(i as u32)?)?;
+        }
+    }
+    Some(n)
+}
+
+// Decode a natural number of any size into its decimal digits
+pub fn decode_nat_decimal(expr: &Expr) -> Option<String> {
+    let bits = decode_bits(expr)?;
+    // Decimal digits, least significant first
+    let mut decimal: Vec<u8> = vec![0];
+    for bit in bits.into_iter().rev() {
+        let mut carry = bit as u8;
+        for d in decimal.iter_mut() {
+            let value = *d * 2 + carry;
+            *d = value % 10;
+            carry = value / 10;
+        }
+        if carry > 0 {
+            decimal.push(carry);
+        }
+    }
+    Some(decimal.iter().rev().map(|d| (d + b'0') as char).collect())
+}
+
+pub fn encode_int(i: i64) -> Expr {
+    encode_pair(encode_bool(i < 0), encode_nat(i.unsigned_abs()))
+}
+
+// Fails if the number does not fit in an i64
+pub fn decode_int(expr: &Expr) -> Option<i64> {
+    let (sign, magnitude) = decode_pair(expr)?;
+    let magnitude = decode_nat(magnitude)?;
+    if decode_bool(sign)? {
+        0i64.checked_sub_unsigned(magnitude)
+    } else {
+        i64::try_from(magnitude).ok()
+    }
+}
+
+// Decode an integer of any size into its decimal digits, with a leading `-` if
+// it is negative
+pub fn decode_int_decimal(expr: &Expr) -> Option<String> {
+    let (sign, magnitude) = decode_pair(expr)?;
+    let digits = decode_nat_decimal(magnitude)?;
+    if decode_bool(sign)? && digits != "0" {
+        Some(format!("-{}", digits))
+    } else {
+        Some(digits)
+    }
+}
+
+pub fn encode_bytes(bytes: &[u8]) -> Expr {
+    encode_list(bytes.iter().map(|b| encode_nat(*b as u64)).collect())
+}
+
+pub fn decode_bytes(expr: &Expr) -> Option<Vec<u8>> {
+    decode_list(expr)?.into_iter()
+        .map(|b| decode_nat(b).and_then(|n| u8::try_from(n).ok()))
+        .collect()
+}
+
+pub fn encode_string(str: &str) -> Expr {
+    encode_bytes(str.as_bytes())
+}
+
+pub fn decode_string(expr: &Expr) -> Option<String> {
+    String::from_utf8(decode_bytes(expr)?).ok()
+}
+
+pub fn encode_list(items: Vec<Expr>) -> Expr {
+    items.into_iter().rev().fold(Expr::leaf(), |rest, item| Expr::fork(item, rest))
+}
+
+pub fn decode_list(expr: &Expr) -> Option<Vec<&Expr>> {
+    let mut items = Vec::new();
+    let mut rest = expr;
+    loop {
+        match rest.children.len() {
+            0 => return Some(items),
+            2 => {
+                items.push(&rest.children[0]);
+                rest = &rest.children[1];
+            }
+            _ => return None,
+        }
+    }
+}
+
+pub fn encode_pair(first: Expr, second: Expr) -> Expr {
+    Expr::fork(first, second)
+}
+
+pub fn decode_pair(expr: &Expr) -> Option<(&Expr, &Expr)> {
+    match expr.children.as_slice() {
+        [first, second] => Some((first, second)),
+        _ => None,
+    }
+}
+
+pub fn encode_option(value: Option<Expr>) -> Expr {
+    match value {
+        None => Expr::leaf(),
+        Some(value) => Expr::stem(value),
+    }
+}
+
+pub fn decode_option(expr: &Expr) -> Option<Option<&Expr>> {
+    match expr.children.as_slice() {
+        [] => Some(None),
+        [value] => Some(Some(value)),
+        _ => None,
+    }
+}
+
+// The shape of a tree to decode
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Tree,
+    Bool,
+    Nat,
+    Int,
+    Bytes,
+    String,
+    List(Box<Type>),
+    Pair(Box<Type>, Box<Type>),
+    Option(Box<Type>),
+}
+
+impl Type {
+    // Parse a type like `nat`, `list(string)` or `pair(int, option(bool))`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        let (head, args) = match name.find('(') {
+            None => (name, Vec::new()),
+            Some(open) => {
+                let inner = name[open + 1..].strip_suffix(')')?;
+                (name[..open].trim(), split_args(inner)?)
+            }
+        };
+        let ty = match (head, args.as_slice()) {
+            ("tree", []) => Type::Tree,
+            ("bool", []) => Type::Bool,
+            ("nat", []) => Type::Nat,
+            ("int", []) => Type::Int,
+            ("bytes", []) => Type::Bytes,
+            ("string", []) => Type::String,
+            ("list", [item]) => Type::List(Box::new(Type::from_name(item)?)),
+            ("pair", [first, second]) => {
+                Type::Pair(Box::new(Type::from_name(first)?), Box::new(Type::from_name(second)?))
+            }
+            ("option", [value]) => Type::Option(Box::new(Type::from_name(value)?)),
+            _ => return None,
+        };
+        Some(ty)
+    }
+}
+
+// Split the arguments of a type at the commas that are not nested in parens
+fn split_args(str: &str) -> Option<Vec<&str>> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in str.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = usize::checked_sub(depth, 1)?,
+            ',' if depth == 0 => {
+                args.push(&str[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(&str[start..]);
+    Some(args)
+}
+
+// A decoded tree
+#[derive(Clone, Debug)]
+pub enum Value {
+    Tree(Expr),
+    Bool(bool),
+    // Decimal digits, so numbers of any size can be represented
+    Nat(String),
+    Int(String),
+    Bytes(Vec<u8>),
+    String(String),
+    List(Vec<Value>),
+    Pair(Box<Value>, Box<Value>),
+    Option(Option<Box<Value>>),
+}
+
+impl Value {
+    // Decode `expr` as a value of type `ty`. Fails if it has a different shape
+    pub fn decode(expr: &Expr, ty: &Type) -> Option<Self> {
+        let value = match ty {
+            Type::Tree => Value::Tree(expr.clone()),
+            Type::Bool => Value::Bool(decode_bool(expr)?),
+            Type::Nat => Value::Nat(decode_nat_decimal(expr)?),
+            Type::Int => Value::Int(decode_int_decimal(expr)?),
+            Type::Bytes => Value::Bytes(decode_bytes(expr)?),
+            Type::String => Value::String(decode_string(expr)?),
+            Type::List(item) => {
+                let items = decode_list(expr)?;
+                Value::List(items.into_iter().map(|e| Value::decode(e, item)).collect::<Option<_>>()?)
+            }
+            Type::Pair(first, second) => {
+                let (a, b) = decode_pair(expr)?;
+                Value::Pair(Box::new(Value::decode(a, first)?), Box::new(Value::decode(b, second)?))
+            }
+            Type::Option(value) => match decode_option(expr)? {
+                None => Value::Option(None),
+                Some(e) => Value::Option(Some(Box::new(Value::decode(e, value)?))),
+            },
+        };
+        Some(value)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Tree(expr) => write!(f, "{}", expr),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Nat(digits) | Value::Int(digits) => write!(f, "{}", digits),
+            Value::Bytes(bytes) => {
+                write!(f, "b\"")?;
+                for b in bytes {
+                    write!(f, "{}", std::ascii::escape_default(*b))?;
+                }
+                write!(f, "\"")
+            }
+            Value::String(str) => write!(f, "{:?}", str),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Pair(a, b) => write!(f, "({}, {})", a, b),
+            Value::Option(None) => write!(f, "none"),
+            Value::Option(Some(value)) => write!(f, "some({})", value),
+        }
+    }
+}
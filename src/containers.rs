@@ -18,11 +18,15 @@ impl<T> Heap<T> {
         }
     }
 
-    // Return the number of occupied entries (only used for debugging)
+    // Return the number of occupied entries
     pub fn len(&self) -> usize {
         self.data.len() - self.empty_count
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     // Return the actual size of the underlying vec (only used for debugging)
     pub fn full_len(&self) -> usize {
         self.data.len()
@@ -45,6 +49,26 @@ impl<T> Heap<T> {
         }
     }
 
+    // Return the `index`th entry, or None if it is empty
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.data.get(index).and_then(|x| x.as_ref())
+    }
+
+    // Put `item` back in the `index`th entry, which must be empty
+    pub fn restore(&mut self, index: usize, item: T) {
+        assert!(self.data[index].is_none(), "The entry should be empty");
+        self.data[index] = Some(item);
+        self.empty_count -= 1;
+    }
+
+    // Remove the entries from `len` on, which must be empty
+    pub fn truncate(&mut self, len: usize) {
+        for item in self.data.drain(len.min(self.data.len())..) {
+            assert!(item.is_none(), "The entry should be empty");
+            self.empty_count -= 1;
+        }
+    }
+
     // Mark the `index`th entry as empty
     pub fn remove(&mut self, index: usize) {
         if self.data[index].is_some() {
@@ -54,6 +78,12 @@ impl<T> Heap<T> {
     }
 }
 
+impl<T> Default for Heap<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> Index<usize> for Heap<T> {
     type Output = T;
 
@@ -86,6 +116,12 @@ impl<T> Stack<T> {
     }
 }
 
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> Index<usize> for Stack<T> {
     type Output = T;
 
@@ -0,0 +1,215 @@
+// An interactive debugger for the VM, which applies one rule at a time. It
+// stops at breakpoints on a rule, on an agent type, or at a number of
+// interactions, shows the heap, the registers and the active pairs, and can go
+// back by reverting the recorded changes of the last steps. Commands are read
+// one per line, and an empty line repeats the last one
+
+use std::io::{self, BufRead, Write};
+
+use crate::agent::*;
+use crate::rules::*;
+use crate::stats::*;
+use crate::vm::*;
+
+const HELP: &str = "\
+s, step [N]         Apply the next N rules (default: 1)
+b, back [N]         Revert the last N rules (default: 1)
+c, continue         Apply rules until a breakpoint or the end
+break rule NAME     Stop before a rule, like RULE_S_T or S-T
+break agent TYPE    Stop before an interaction with an agent of type TYPE
+break step N        Stop after N interactions
+breakpoints         List the breakpoints
+delete N            Delete the breakpoint numbered N
+heap, regs, pairs   Print the heap, the registers or the active pairs
+readback            Print the tree connected to the interface
+where               Print the number of interactions and the next pair
+q, quit             Stop debugging";
+
+// The most steps that can be reverted, so a long run does not keep a record of
+// every step
+pub const UNDO_LIMIT: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Breakpoint {
+    // Before an interaction of the rule at this index in `RULES`
+    Rule(usize),
+    // Before an interaction of an agent of this type
+    Agent(AgentType),
+    // When this many interactions have been applied
+    Step(u64),
+}
+
+impl Breakpoint {
+    // Parse the arguments of `break`, like `rule S-T`
+    pub fn from_args(kind: &str, value: &str) -> Option<Self> {
+        match kind {
+            "rule" => {
+                let name = value.to_uppercase().replace('-', "_");
+                let name = name.strip_prefix("RULE_").unwrap_or(&name);
+                RULES_NAME.iter().position(|n| n.strip_prefix("RULE_") == Some(name)).map(Breakpoint::Rule)
+            }
+            "agent" => {
+                let types = [AgentType::L, AgentType::S, AgentType::F, AgentType::E, AgentType::D,
+                    AgentType::A, AgentType::T, AgentType::Q, AgentType::I];
+                types.into_iter().find(|t| format!("{:?}", t) == value.to_uppercase()).map(Breakpoint::Agent)
+            }
+            "step" => value.parse().ok().map(Breakpoint::Step),
+            _ => None,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Breakpoint::Rule(index) => format!("rule {}", RULES_NAME[*index]),
+            Breakpoint::Agent(agent_type) => format!("agent {:?}", agent_type),
+            Breakpoint::Step(step) => format!("step {}", step),
+        }
+    }
+}
+
+pub struct Debugger {
+    pub vm: VM,
+    breakpoints: Vec<Breakpoint>,
+    last_command: String,
+}
+
+impl Debugger {
+    // Debug `vm`. Its last `UNDO_LIMIT` steps are recorded from now on, so they
+    // can be reverted
+    pub fn new(mut vm: VM) -> Self {
+        vm.set_undo(true);
+        vm.set_undo_limit(UNDO_LIMIT);
+        Self {vm, breakpoints: Vec::new(), last_command: String::new()}
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    // Return the first breakpoint the VM is at
+    fn hit_breakpoint(&self) -> Option<Breakpoint> {
+        let pair = self.vm.next_pair().map(|(left, right)| {
+            (self.vm.agent_type(left).unwrap(), self.vm.agent_type(right).unwrap())
+        });
+        self.breakpoints.iter().copied().find(|breakpoint| match (breakpoint, pair) {
+            (Breakpoint::Rule(index), Some((left, right))) => rule_index(left, right) == Some(*index),
+            (Breakpoint::Agent(agent_type), Some((left, right))) => left == *agent_type || right == *agent_type,
+            (Breakpoint::Step(step), _) => self.vm.interaction_count() == *step,
+            _ => false,
+        })
+    }
+
+    // Apply at most `count` rules, stopping at breakpoints after the first
+    // one. Return the breakpoint it stopped at, if any
+    pub fn run(&mut self, count: u64) -> Option<Breakpoint> {
+        for i in 0..count {
+            if i > 0 {
+                if let Some(breakpoint) = self.hit_breakpoint() {
+                    return Some(breakpoint);
+                }
+            }
+            // Finished
+            self.vm.next_pair()?;
+            self.vm.step();
+        }
+        self.hit_breakpoint()
+    }
+
+    // Describe where the VM is
+    fn location(&self) -> String {
+        match self.vm.next_pair() {
+            None => format!("Finished after {} interactions", self.vm.interaction_count()),
+            Some((left, right)) => {
+                let (left_type, right_type) = (self.vm.agent_type(left).unwrap(), self.vm.agent_type(right).unwrap());
+                let rule = rule_index(left_type, right_type).map_or("no rule".to_string(), short_rule_name);
+                format!("Step {}, next: {} ({:?}) >< {} ({:?})  {}",
+                    self.vm.interaction_count(), left, left_type, right, right_type, rule)
+            }
+        }
+    }
+
+    // Run a command. Return false if it is `quit`
+    pub fn command(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let line = if line.trim().is_empty() { self.last_command.clone() } else { line.trim().to_string() };
+        self.last_command = line.clone();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let count = || words.get(1).map_or(Some(1), |n| n.parse().ok());
+        match words.as_slice() {
+            [] => {}
+            ["q" | "quit"] => return Ok(false),
+            ["s" | "step"] | ["s" | "step", _] | ["c" | "continue"] => {
+                let count = if words[0].starts_with('c') { Some(u64::MAX) } else { count() };
+                match count {
+                    Some(count) => {
+                        if let Some(breakpoint) = self.run(count) {
+                            writeln!(out, "Breakpoint: {}", breakpoint.describe())?;
+                        }
+                        writeln!(out, "{}", self.location())?;
+                    }
+                    None => writeln!(out, "Expected a number: {}", words[1])?,
+                }
+            }
+            ["b" | "back"] | ["b" | "back", _] => match count() {
+                Some(count) => {
+                    let mut reverted = 0;
+                    while reverted < count && self.vm.step_back() {
+                        reverted += 1;
+                    }
+                    if reverted < count {
+                        writeln!(out, "No more steps to revert")?;
+                    }
+                    writeln!(out, "{}", self.location())?;
+                }
+                None => writeln!(out, "Expected a number: {}", words[1])?,
+            },
+            ["break", kind, value] => match Breakpoint::from_args(kind, value) {
+                Some(breakpoint) => {
+                    self.breakpoints.push(breakpoint);
+                    writeln!(out, "Breakpoint {}: {}", self.breakpoints.len(), breakpoint.describe())?;
+                }
+                None => writeln!(out, "Unknown breakpoint: {} {}", kind, value)?,
+            },
+            ["breakpoints"] => {
+                for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+                    writeln!(out, "{}: {}", i + 1, breakpoint.describe())?;
+                }
+            }
+            ["delete", n] => match n.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= self.breakpoints.len() => {
+                    self.breakpoints.remove(n - 1);
+                }
+                _ => writeln!(out, "No breakpoint {}", n)?,
+            },
+            ["heap"] => write!(out, "{}", self.vm.dump_heap())?,
+            ["regs"] => write!(out, "{}", self.vm.dump_registers())?,
+            ["pairs"] => write!(out, "{}", self.vm.dump_active_pairs())?,
+            ["readback"] => match self.vm.try_readback() {
+                Ok(expr) => writeln!(out, "{}", expr)?,
+                Err(err) => writeln!(out, "Readback failed: {}", err)?,
+            },
+            ["where"] => writeln!(out, "{}", self.location())?,
+            ["help"] => writeln!(out, "{}", HELP)?,
+            _ => writeln!(out, "Unknown command: {} (try help)", line)?,
+        }
+        Ok(true)
+    }
+
+    // Read commands from `input` until `quit` or the end of the input
+    pub fn repl(&mut self, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{}", self.location())?;
+        write!(out, "(tcdb) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            if !self.command(&line?, out)? {
+                return Ok(());
+            }
+            write!(out, "(tcdb) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+}
@@ -0,0 +1,92 @@
+// Explains a reduction by printing every rule the reference reducer applies,
+// with the redex, what it is rewritten to, and the whole tree afterwards with
+// the rewritten subtree between `«` and `»`:
+//
+//   1. S: t (t (t t)) t t => t t t (t t t)
+//      «t t t (t t t)»
+//
+// With a depth limit, subtrees that are deeper than the limit are written as
+// `…(size N)`. In the whole tree, the depth is counted from the nearest tree on
+// the way to the rewritten subtree, so the place of the rewrite is always shown
+
+use std::io::{self, Write};
+
+use crate::expr::*;
+use crate::reduce::*;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExplainOptions {
+    pub max_depth: Option<usize>,
+}
+
+// Write `expr` on one line. The subtree at `path` (see `Rewrite::whole_tree`),
+// if any, is marked
+fn write_tree(expr: &Expr, options: &ExplainOptions, path: Option<&[usize]>) -> String {
+    enum Task<'a> {
+        // A tree, its depth, and how far down `path` it is if it is on it
+        Node(&'a Expr, usize, Option<usize>),
+        Text(&'static str),
+    }
+    let is_elided = |expr: &Expr, depth: usize, level: Option<usize>| {
+        level.is_none() && !expr.children.is_empty() && options.max_depth.is_some_and(|max| depth > max)
+    };
+    let mut str = String::new();
+    let mut tasks = vec![Task::Node(expr, 0, path.map(|_| 0))];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Node(expr, depth, level) => {
+                let is_marked = level.is_some() && level == path.map(|p| p.len());
+                if is_elided(expr, depth, level) {
+                    str.push_str(&format!("…(size {})", expr.get_size()));
+                    continue;
+                }
+                if is_marked {
+                    str.push('«');
+                    tasks.push(Task::Text("»"));
+                }
+                str.push('t');
+                for (i, c) in expr.children.iter().enumerate().rev() {
+                    let on_path = match (level, path) {
+                        (Some(level), Some(path)) if level < path.len() && path[level] == i => Some(level + 1),
+                        _ => None,
+                    };
+                    // The depth restarts on the path
+                    let c_depth = if level.is_some() { 1 } else { depth + 1 };
+                    let c_depth = if on_path.is_some() { 0 } else { c_depth };
+                    if c.children.is_empty() || is_elided(c, c_depth, on_path) {
+                        tasks.push(Task::Node(c, c_depth, on_path));
+                    } else {
+                        tasks.extend([Task::Text(")"), Task::Node(c, c_depth, on_path), Task::Text("(")]);
+                    }
+                    tasks.push(Task::Text(" "));
+                }
+            }
+            Task::Text(text) => str.push_str(text),
+        }
+    }
+    str
+}
+
+// Reduce `expr` to normal form, writing every rule applied to `out`, and return
+// the normal form
+pub fn explain(expr: Expr, options: &ExplainOptions, out: &mut impl Write) -> io::Result<Expr> {
+    let mut step = 0;
+    let mut error = None;
+    let mut observer = |rewrite: &Rewrite| {
+        if error.is_some() {
+            return;
+        }
+        step += 1;
+        let (tree, path) = rewrite.whole_tree();
+        let indent = " ".repeat(step.to_string().len() + 2);
+        let result = writeln!(out, "{}. {}: {} => {}\n{}{}", step, rewrite.rule.name(),
+            write_tree(rewrite.redex, options, None), write_tree(rewrite.result, options, None),
+            indent, write_tree(&tree, options, Some(&path)));
+        error = result.err();
+    };
+    let result = reduce_with(expr, Some(&mut observer));
+    match error {
+        Some(err) => Err(err),
+        None => Ok(result),
+    }
+}
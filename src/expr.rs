@@ -1,4 +1,12 @@
-#[derive(Debug, Clone)]
+use std::fmt;
+
+// A tree is a leaf with its children applied to it one by one: no children is a
+// leaf, one is a stem, two is a fork, and more are applications.
+//
+// Trees can be very deep (a long list or a big number is a chain of forks), so
+// nothing that walks over the children is recursive. Every traversal keeps its
+// own worklist on the heap instead, and only the available memory limits the
+// depth of a tree
 pub struct Expr {
     pub children: Vec<Expr>,
 }
@@ -8,22 +16,94 @@ impl Expr {
         Self { children }
     }
 
-    pub fn to_string(&self) -> String {
-        let mut str = String::from("t");
-        for c in &self.children {
-            let child_str = c.to_string();
-            if c.children.len() > 0 {
-                str += "(";
-                str += &child_str;
-                str += ")";
-            } else {
-                str += &child_str;
+    pub fn leaf() -> Self {
+        Self::new(vec![])
+    }
+
+    pub fn stem(child: Expr) -> Self {
+        Self::new(vec![child])
+    }
+
+    pub fn fork(left: Expr, right: Expr) -> Self {
+        Self::new(vec![left, right])
+    }
+
+    pub fn get_size(&self) -> u64 {
+        let mut size = 0;
+        let mut stack = vec![self];
+        while let Some(expr) = stack.pop() {
+            size += 1;
+            stack.extend(&expr.children);
+        }
+        size
+    }
+}
+
+impl Clone for Expr {
+    fn clone(&self) -> Self {
+        enum Task<'a> {
+            Visit(&'a Expr),
+            // Pop this many copies from the results and make them the children
+            // of a new node
+            Build(usize),
+        }
+        let mut tasks = vec![Task::Visit(self)];
+        let mut results: Vec<Expr> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(expr) => {
+                    tasks.push(Task::Build(expr.children.len()));
+                    tasks.extend(expr.children.iter().rev().map(Task::Visit));
+                }
+                Task::Build(n) => {
+                    let children = results.split_off(results.len() - n);
+                    results.push(Expr::new(children));
+                }
             }
         }
-        str
+        results.pop().unwrap()
     }
+}
 
-    pub fn get_size(&self) -> u64 {
-        1 + self.children.iter().map(|c| c.get_size()).sum::<u64>()
+impl Drop for Expr {
+    fn drop(&mut self) {
+        // Detach the descendants before they are dropped, so every node is
+        // dropped without any children
+        let mut stack = std::mem::take(&mut self.children);
+        while let Some(mut expr) = stack.pop() {
+            stack.append(&mut expr.children);
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        enum Task<'a> {
+            Node(&'a Expr),
+            Text(&'static str),
+        }
+        let mut tasks = vec![Task::Node(self)];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Node(expr) => {
+                    write!(f, "t")?;
+                    for c in expr.children.iter().rev() {
+                        if !c.children.is_empty() {
+                            tasks.extend([Task::Text(")"), Task::Node(c), Task::Text("(")]);
+                        } else {
+                            tasks.push(Task::Node(c));
+                        }
+                    }
+                }
+                Task::Text(text) => write!(f, "{}", text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Expr({})", self)
     }
 }
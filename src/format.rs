@@ -0,0 +1,277 @@
+// Rewrites source files in a canonical form, for `--fmt`:
+// - one space between the parts of an application, and only the parentheses
+//   that are needed, so `((t t) (t))` is written `t t t`
+// - nested lambdas are merged, so `\x. \y. x` is written `\x y. x`
+// - a definition that does not fit in the line width is broken the same way as
+//   trees are by the pretty printer, and long lists get an item per line
+// - comments are kept. A comment inside an expression is moved before the
+//   import or definition it is in
+// - runs of blank lines become a single blank line
+//
+// The source is read with the parser of parse.rs, but only its syntax is
+// checked: names are not resolved and imports are not loaded
+
+use crate::lexer::*;
+use crate::parse::*;
+use crate::pretty::*;
+
+enum Block<'a> {
+    Comment(&'a str),
+    // A comment at the end of the line of the previous block
+    TrailingComment(&'a str),
+    Blank,
+    Item(&'a ItemKind),
+}
+
+fn is_before(a: Span, b: Span) -> bool {
+    (a.line, a.column) < (b.line, b.column)
+}
+
+// Add a comment that is not inside an item. `last_line` is the line the last
+// block ends on
+fn push_comment<'a>(blocks: &mut Vec<Block<'a>>, comment: &'a Comment, last_line: &mut usize) {
+    if comment.span.line == *last_line && !matches!(blocks.last(), None | Some(Block::Comment(_))) {
+        blocks.push(Block::TrailingComment(&comment.text));
+    } else {
+        if comment.span.line > *last_line + 1 && !matches!(blocks.last(), None | Some(Block::Blank)) {
+            blocks.push(Block::Blank);
+        }
+        blocks.push(Block::Comment(&comment.text));
+    }
+    *last_line = comment.span.line;
+}
+
+// Put the comments and the items of `file` in the order they are written in.
+// A comment inside an item goes before it, and a run of blank lines becomes a
+// single blank line
+fn blocks(file: &SourceFile) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    let mut comments = file.comments.iter().peekable();
+    // The line the last block ends on
+    let mut last_line = 1;
+    for item in &file.items {
+        while let Some(comment) = comments.next_if(|c| is_before(c.span, item.start)) {
+            push_comment(&mut blocks, comment, &mut last_line);
+        }
+        if item.start.line > last_line + 1 && !matches!(blocks.last(), None | Some(Block::Blank)) {
+            blocks.push(Block::Blank);
+        }
+        while let Some(comment) = comments.next_if(|c| is_before(c.span, item.end)) {
+            blocks.push(Block::Comment(&comment.text));
+        }
+        blocks.push(Block::Item(&item.kind));
+        last_line = item.end.line;
+    }
+    for comment in comments {
+        push_comment(&mut blocks, comment, &mut last_line);
+    }
+    blocks
+}
+
+fn write_string(bytes: &[u8]) -> String {
+    let mut str = "\"".to_string();
+    for c in String::from_utf8_lossy(bytes).chars() {
+        match c {
+            '\n' => str.push_str("\\n"),
+            '\t' => str.push_str("\\t"),
+            '\r' => str.push_str("\\r"),
+            '\0' => str.push_str("\\0"),
+            '\\' => str.push_str("\\\\"),
+            '"' => str.push_str("\\\""),
+            c => str.push(c),
+        }
+    }
+    str.push('"');
+    str
+}
+
+// An argument needs parentheses if it is an application, or a lambda that is
+// not the last argument
+fn needs_parens(arg: &Syntax, is_last: bool) -> bool {
+    match arg {
+        Syntax::App(_) => true,
+        Syntax::Lam(..) => !is_last,
+        _ => false,
+    }
+}
+
+// The text of a syntax that has no parts
+fn atom(syntax: &Syntax) -> String {
+    match syntax {
+        Syntax::Leaf => "t".to_string(),
+        Syntax::Name(_, name) => name.clone(),
+        Syntax::Qualified(_, module_name, name) => format!("{}.{}", module_name, name),
+        Syntax::Number(digits) => {
+            let digits = digits.trim_start_matches('0');
+            if digits.is_empty() { "0" } else { digits }.to_string()
+        }
+        Syntax::Str(bytes) => write_string(bytes),
+        Syntax::List(_) | Syntax::App(_) | Syntax::Lam(..) => unreachable!("not an atom"),
+    }
+}
+
+// Write `syntax` on one line into `str`. Gives up and returns false as soon as
+// `str` is longer than `budget` characters. Like the pretty printer, it uses
+// its own stack, so deeply nested sources can be formatted
+fn flat(syntax: &Syntax, budget: usize, str: &mut String) -> bool {
+    enum Task<'a> {
+        Node(&'a Syntax),
+        Text(String),
+    }
+    let mut len = 0;
+    let mut tasks = vec![Task::Node(syntax)];
+    while let Some(task) = tasks.pop() {
+        let text = match task {
+            Task::Node(Syntax::List(items)) => {
+                tasks.push(Task::Text("]".to_string()));
+                for (i, item) in items.iter().enumerate().rev() {
+                    tasks.push(Task::Node(item));
+                    if i > 0 {
+                        tasks.push(Task::Text(", ".to_string()));
+                    }
+                }
+                "[".to_string()
+            }
+            Task::Node(Syntax::App(parts)) => {
+                for (i, part) in parts.iter().enumerate().rev() {
+                    if needs_parens(part, i == parts.len() - 1) {
+                        tasks.extend([Task::Text(")".to_string()), Task::Node(part), Task::Text("(".to_string())]);
+                    } else {
+                        tasks.push(Task::Node(part));
+                    }
+                    if i > 0 {
+                        tasks.push(Task::Text(" ".to_string()));
+                    }
+                }
+                String::new()
+            }
+            Task::Node(Syntax::Lam(vars, body)) => {
+                tasks.push(Task::Node(body));
+                format!("\\{}. ", vars.join(" "))
+            }
+            Task::Node(syntax) => atom(syntax),
+            Task::Text(text) => text,
+        };
+        len += text.chars().count();
+        if len > budget {
+            return false;
+        }
+        str.push_str(&text);
+    }
+    true
+}
+
+struct Printer<'a> {
+    options: &'a PrettyOptions,
+    out: String,
+    column: usize,
+}
+
+impl Printer<'_> {
+    fn push(&mut self, text: &str) {
+        self.out.push_str(text);
+        self.column += text.chars().count();
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+        self.column = indent;
+    }
+
+    // Write `syntax` starting at the current column. Lines that are broken
+    // inside it are indented relative to `indent`
+    fn print(&mut self, syntax: &Syntax, indent: usize) {
+        enum Task<'a> {
+            Node(&'a Syntax, usize),
+            Text(&'static str),
+            Newline(usize),
+        }
+        let mut tasks = vec![Task::Node(syntax, indent)];
+        while let Some(task) = tasks.pop() {
+            let (syntax, indent) = match task {
+                Task::Node(syntax, indent) => (syntax, indent),
+                Task::Text(text) => {
+                    self.push(text);
+                    continue;
+                }
+                Task::Newline(indent) => {
+                    self.newline(indent);
+                    continue;
+                }
+            };
+            let mut text = String::new();
+            if flat(syntax, self.options.width.saturating_sub(self.column), &mut text) {
+                self.push(&text);
+                continue;
+            }
+            // As in the pretty printer, the indentation stops growing at half
+            // the width
+            let child_indent = (indent + self.options.indent).min(self.options.width / 2);
+            match syntax {
+                Syntax::App(parts) => {
+                    for (i, part) in parts.iter().enumerate().rev() {
+                        let part_indent = if i == 0 { indent } else { child_indent };
+                        if needs_parens(part, i == parts.len() - 1) {
+                            tasks.extend([Task::Text(")"), Task::Node(part, part_indent), Task::Text("(")]);
+                        } else {
+                            tasks.push(Task::Node(part, part_indent));
+                        }
+                        if i > 0 {
+                            tasks.push(Task::Newline(child_indent));
+                        }
+                    }
+                }
+                Syntax::List(items) => {
+                    self.push("[");
+                    tasks.extend([Task::Text("]"), Task::Newline(indent)]);
+                    for item in items.iter().rev() {
+                        tasks.extend([Task::Text(","), Task::Node(item, child_indent), Task::Newline(child_indent)]);
+                    }
+                }
+                Syntax::Lam(vars, body) => {
+                    self.push(&format!("\\{}.", vars.join(" ")));
+                    tasks.extend([Task::Node(body, child_indent), Task::Newline(child_indent)]);
+                }
+                syntax => self.push(&atom(syntax)),
+            }
+        }
+    }
+}
+
+// Rewrite the source of a program or a module in canonical form. Only the width
+// and the indentation of `options` are used
+pub fn format_source(str: &str, options: &PrettyOptions) -> Result<String, ParseError> {
+    let file = parse_source(str)?;
+    let blocks = blocks(&file);
+
+    let mut printer = Printer {options, out: String::new(), column: 0};
+    for (i, block) in blocks.iter().enumerate() {
+        if i > 0 && !matches!(block, Block::TrailingComment(_)) {
+            printer.newline(0);
+        }
+        match block {
+            Block::Comment(text) => printer.push(&format!("#{}", text)),
+            Block::TrailingComment(text) => printer.push(&format!(" #{}", text)),
+            Block::Blank => {}
+            Block::Item(ItemKind::Import(path, alias)) => {
+                match path {
+                    ModulePath::Str(bytes) => printer.push(&format!("import {}", write_string(bytes))),
+                    ModulePath::Names(names) => printer.push(&format!("import {}", names.join("/"))),
+                }
+                if let Some(alias) = alias {
+                    printer.push(&format!(" as {}", alias));
+                }
+            }
+            Block::Item(ItemKind::Definition(name, syntax)) => {
+                printer.push(&format!("{} = ", name));
+                printer.print(syntax, 0);
+            }
+            Block::Item(ItemKind::Expr(syntax)) => printer.print(syntax, 0),
+        }
+    }
+    if !blocks.is_empty() {
+        printer.newline(0);
+    }
+    Ok(printer.out)
+}
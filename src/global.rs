@@ -8,6 +8,8 @@ pub const MAX_AGENTS_CREATED: u8 = 4;
 pub const MAX_AGENT_REG_SIZE: u8 = MAX_AUX_NUM_LEFT + MAX_AUX_NUM_RIGHT + MAX_AGENTS_CREATED + 2;
 // pub const MAX_PORT_REG_SIZE: u8 = MAX_AUX_NUM_LEFT + MAX_AUX_NUM_RIGHT;
 pub const UNASSIGNED_PORT: HeapAddress = HeapAddress::MAX;
+// The VM looks at its cancellation token once every this many interactions
+pub const CANCEL_CHECK_INTERVAL: u64 = 1024;
 
 pub type RegAddress = u8;
 pub type HeapAddress = usize;
@@ -15,6 +17,11 @@ pub type HeapAddress = usize;
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PortNum {P0, P1, P2, P3, Main}
 
+impl PortNum {
+    // In the order of the ports of an agent
+    pub const ALL: [PortNum; 5] = [PortNum::P0, PortNum::P1, PortNum::P2, PortNum::P3, PortNum::Main];
+}
+
 // https://stackoverflow.com/questions/74586162/how-to-import-use-macro-from-different-module-in-the-same-crate
 #[macro_export]
 macro_rules! debug_log {
@@ -0,0 +1,187 @@
+// Lambda terms and their translation to tree calculus by bracket abstraction.
+// The reduction rules are the ones implemented in rules.rs (and used on
+// https://treecalcul.us), so the S rule is t (t a) b c = a c (b c)
+
+use crate::arena::*;
+
+// Which bracket abstraction algorithm to use when removing a lambda. They all
+// give extensionally equal programs, but their sizes can be very different
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Strategy {
+    // [x]x = I, [x]y = K y, [x](M N) = d{[x]M} ([x]N)
+    Naive,
+    // Naive, but [x]M = K M whenever x does not occur in M
+    Constant,
+    // Constant, but [x](M x) = M whenever x does not occur in M
+    #[default]
+    Eta,
+}
+
+impl Strategy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "naive" => Some(Strategy::Naive),
+            "constant" => Some(Strategy::Constant),
+            "eta" => Some(Strategy::Eta),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Term {
+    Var(String),
+    // A closed tree, like `t` or a reference to an earlier definition
+    Tree(NodeId),
+    App(Box<Term>, Box<Term>),
+    Lam(String, Box<Term>),
+}
+
+fn leaf(arena: &mut Arena) -> Term {
+    Term::Tree(arena.leaf())
+}
+
+// K = t t
+fn k(arena: &mut Arena) -> Term {
+    let leaf = arena.leaf();
+    Term::Tree(arena.stem(leaf))
+}
+
+// I = t (t K) K
+fn i(arena: &mut Arena) -> Term {
+    let leaf = arena.leaf();
+    let k = arena.stem(leaf);
+    let stem_k = arena.stem(k);
+    Term::Tree(arena.fork(stem_k, k))
+}
+
+// d{a} b = t (t a) b, and d{a} b c = a c (b c)
+fn d(a: Term, b: Term, arena: &mut Arena) -> Term {
+    Term::app(Term::app(leaf(arena), Term::app(leaf(arena), a)), b)
+}
+
+impl Term {
+    pub fn app(f: Term, x: Term) -> Term {
+        Term::App(Box::new(f), Box::new(x))
+    }
+
+    fn occurs(&self, var: &str) -> bool {
+        let mut stack = vec![self];
+        while let Some(term) = stack.pop() {
+            match term {
+                Term::Var(name) if name == var => return true,
+                Term::App(f, x) => {
+                    stack.push(x);
+                    stack.push(f);
+                }
+                Term::Lam(name, body) if name != var => stack.push(body),
+                _ => {}
+            }
+        }
+        false
+    }
+
+    // Compute [var]self. `self` must not contain lambdas
+    fn abstract_var(self, var: &str, strategy: Strategy, arena: &mut Arena) -> Term {
+        enum Task {
+            Abstract(Term),
+            // Combine the abstractions of a function and its argument
+            D,
+        }
+        let mut tasks = vec![Task::Abstract(self)];
+        let mut done = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Abstract(term) if strategy != Strategy::Naive && !term.occurs(var) => {
+                    done.push(Term::app(k(arena), term));
+                }
+                Task::Abstract(Term::Var(name)) if name == var => done.push(i(arena)),
+                Task::Abstract(Term::App(f, x)) => {
+                    if strategy == Strategy::Eta && !f.occurs(var) &&
+                        matches!(x.as_ref(), Term::Var(name) if name == var)
+                    {
+                        done.push(*f);
+                        continue;
+                    }
+                    tasks.push(Task::D);
+                    tasks.push(Task::Abstract(*x));
+                    tasks.push(Task::Abstract(*f));
+                }
+                Task::Abstract(Term::Lam(..)) => unreachable!("lambdas are removed before abstraction"),
+                Task::Abstract(term) => done.push(Term::app(k(arena), term)),
+                Task::D => {
+                    let x = done.pop().unwrap();
+                    let f = done.pop().unwrap();
+                    done.push(d(f, x, arena));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+
+    // Remove every lambda, innermost first
+    fn eliminate(self, strategy: Strategy, arena: &mut Arena) -> Term {
+        enum Task {
+            Eliminate(Term),
+            App,
+            // Abstract the variable from the body on top of `done`
+            Abstract(String),
+        }
+        let mut tasks = vec![Task::Eliminate(self)];
+        let mut done = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Eliminate(Term::App(f, x)) => {
+                    tasks.push(Task::App);
+                    tasks.push(Task::Eliminate(*x));
+                    tasks.push(Task::Eliminate(*f));
+                }
+                Task::Eliminate(Term::Lam(var, body)) => {
+                    tasks.push(Task::Abstract(var));
+                    tasks.push(Task::Eliminate(*body));
+                }
+                Task::Eliminate(term) => done.push(term),
+                Task::App => {
+                    let x = done.pop().unwrap();
+                    let f = done.pop().unwrap();
+                    done.push(Term::app(f, x));
+                }
+                Task::Abstract(var) => {
+                    let body = done.pop().unwrap();
+                    done.push(body.abstract_var(&var, strategy, arena));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+
+    // Translate a closed term into a tree in `arena`. The term of a long list
+    // is as deep as the list is long, so this and the other traversals of
+    // terms use their own stack instead of recursion
+    pub fn into_node(self, strategy: Strategy, arena: &mut Arena) -> NodeId {
+        enum Task {
+            Translate(Term),
+            App,
+        }
+        let mut tasks = vec![Task::Translate(self.eliminate(strategy, arena))];
+        let mut done = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Translate(Term::Tree(id)) => done.push(id),
+                Task::Translate(Term::App(f, x)) => {
+                    tasks.push(Task::App);
+                    tasks.push(Task::Translate(*x));
+                    tasks.push(Task::Translate(*f));
+                }
+                Task::Translate(Term::Var(name)) => panic!("free variable `{}` in a closed term", name),
+                Task::Translate(Term::Lam(..)) => unreachable!("lambdas are removed before translation"),
+                Task::App => {
+                    let x = done.pop().unwrap();
+                    let f = done.pop().unwrap();
+                    done.push(arena.app(f, x));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+
+use crate::parse::*;
+
+// A position in the source text. Both fields start at 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Leaf,
+    Import,
+    Ident(String),
+    // Decimal digits of a natural number literal
+    Number(String),
+    // UTF-8 bytes of a string literal, with escapes resolved
+    Str(Vec<u8>),
+    Equals,
+    Lambda,
+    Dot,
+    Slash,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    // Text of a `#` comment after the `#`. Only produced by
+    // `Lexer::with_comments`
+    Comment(String),
+    Eof,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Leaf => write!(f, "`t`"),
+            TokenKind::Import => write!(f, "`import`"),
+            TokenKind::Ident(name) => write!(f, "`{}`", name),
+            TokenKind::Number(digits) => write!(f, "`{}`", digits),
+            TokenKind::Str(bytes) => write!(f, "{:?}", String::from_utf8_lossy(bytes)),
+            TokenKind::Equals => write!(f, "`=`"),
+            TokenKind::Lambda => write!(f, "`\\`"),
+            TokenKind::Dot => write!(f, "`.`"),
+            TokenKind::Slash => write!(f, "`/`"),
+            TokenKind::LParen => write!(f, "`(`"),
+            TokenKind::RParen => write!(f, "`)`"),
+            TokenKind::LBracket => write!(f, "`[`"),
+            TokenKind::RBracket => write!(f, "`]`"),
+            TokenKind::Comma => write!(f, "`,`"),
+            TokenKind::Comment(_) => write!(f, "a comment"),
+            TokenKind::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\''
+}
+
+pub struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    column: usize,
+    keep_comments: bool,
+}
+
+impl Lexer {
+    pub fn new(src: &str) -> Self {
+        Self {chars: src.chars().collect(), pos: 0, line: 1, column: 1, keep_comments: false}
+    }
+
+    // A lexer that returns comments as tokens instead of skipping them
+    pub fn with_comments(src: &str) -> Self {
+        Self {keep_comments: true, ..Self::new(src)}
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn span(&self) -> Span {
+        Span {line: self.line, column: self.column}
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    // Skip whitespace and `#` line comments
+    fn skip_trivia(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' && self.keep_comments {
+                break;
+            } else if c == '#' {
+                while let Some(c) = self.bump() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    // A word made only of `t`s (like `ttt`, as printed by `Expr::to_string`)
+    // is a sequence of leaves, not a name
+    fn at_leaf_run(&self) -> bool {
+        self.chars[self.pos..].iter()
+            .take_while(|c| is_ident_continue(**c))
+            .all(|c| *c == 't')
+    }
+
+    fn lex_ident(&mut self, first: char) -> String {
+        let mut name = first.to_string();
+        while let Some(c) = self.peek() {
+            if !is_ident_continue(c) {
+                break;
+            }
+            name.push(c);
+            self.bump();
+        }
+        name
+    }
+
+    fn lex_number(&mut self, first: char) -> String {
+        let mut digits = first.to_string();
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            digits.push(c);
+            self.bump();
+        }
+        digits
+    }
+
+    // Lex a string literal after its opening quote
+    fn lex_string(&mut self, start: Span) -> Result<Vec<u8>, ParseError> {
+        let mut str = String::new();
+        loop {
+            let span = self.span();
+            let c = match self.bump() {
+                None => return Err(ParseError::new(start, "unterminated string".to_string(), "a closing `\"`")),
+                Some(c) => c,
+            };
+            match c {
+                '"' => return Ok(str.into_bytes()),
+                '\\' => {
+                    let escaped = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('\\') => '\\',
+                        Some('"') => '"',
+                        other => {
+                            let found = other.map_or("end of input".to_string(), |c| format!("`\\{}`", c));
+                            return Err(ParseError::new(span, found, "one of `\\n \\t \\r \\0 \\\\ \\\"`"));
+                        }
+                    };
+                    str.push(escaped);
+                }
+                c => str.push(c),
+            }
+        }
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, ParseError> {
+        self.skip_trivia();
+        let span = self.span();
+        let c = match self.bump() {
+            None => return Ok(Token {kind: TokenKind::Eof, span}),
+            Some(c) => c,
+        };
+        let kind = match c {
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            '=' => TokenKind::Equals,
+            '\\' | 'λ' => TokenKind::Lambda,
+            '.' => TokenKind::Dot,
+            '/' => TokenKind::Slash,
+            '[' => TokenKind::LBracket,
+            ']' => TokenKind::RBracket,
+            ',' => TokenKind::Comma,
+            '"' => TokenKind::Str(self.lex_string(span)?),
+            '#' => {
+                let mut text = String::new();
+                while let Some(c) = self.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    text.push(c);
+                    self.bump();
+                }
+                TokenKind::Comment(text.trim_end().to_string())
+            }
+            c if c.is_ascii_digit() => TokenKind::Number(self.lex_number(c)),
+            't' if self.at_leaf_run() => TokenKind::Leaf,
+            '△' | 'Δ' => TokenKind::Leaf,
+            c if is_ident_start(c) => {
+                let name = self.lex_ident(c);
+                if name == "import" {
+                    TokenKind::Import
+                } else {
+                    TokenKind::Ident(name)
+                }
+            }
+            c => {
+                return Err(ParseError::new(span, format!("`{}`", c),
+                    "`t`, a name, a literal or one of `( ) [ ] , = \\ . /`"));
+            }
+        };
+        Ok(Token {kind, span})
+    }
+
+    // Lex the whole input. The last token is always `Eof`
+    pub fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            let is_eof = token.kind == TokenKind::Eof;
+            tokens.push(token);
+            if is_eof {
+                return Ok(tokens);
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+// Tree calculus evaluator based on interaction nets. The `tc-inet-rust` binary
+// (main.rs) is a command-line front-end to this library
+
+pub mod agent;
+pub mod arena;
+pub mod binary;
+pub mod code;
+pub mod codec;
+pub mod compiler;
+pub mod containers;
+pub mod debugger;
+pub mod explain;
+pub mod expr;
+pub mod format;
+pub mod global;
+pub mod lambda;
+pub mod lexer;
+pub mod machine;
+pub mod memo;
+pub mod module;
+pub mod notation;
+pub mod parse;
+pub mod pretty;
+pub mod reduce;
+pub mod rules;
+pub mod session;
+pub mod stats;
+pub mod trace;
+#[cfg(test)]
+mod test;
+pub mod vm;
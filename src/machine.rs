@@ -0,0 +1,175 @@
+// A stack machine for tree calculus, which evaluates trees without building an
+// interaction net. A tree is compiled into postfix code with two instructions:
+// `Leaf` pushes a leaf, and `Apply` pops an argument and a function and pushes
+// the result of the application. `t (t t) t` is compiled into
+//   Leaf Leaf Leaf Apply Apply Leaf Apply
+//
+// Values are leaves, stems and forks stored in a vector, and refer to their
+// children by index, so applying a rule only copies indices. Values are never
+// freed until the machine is dropped.
+//
+// Like the memoizing evaluator, arguments are reduced to normal form before
+// they are applied. The rules are the same as the VM's:
+//   t t b c             = b
+//   t (t a) b c         = a c (b c)
+//   t (t w x) y t       = w
+//   t (t w x) y (t u)   = x u
+//   t (t w x) y (t u v) = y u v
+
+use crate::expr::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Leaf,
+    Apply,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Value {
+    Leaf,
+    Stem(u32),
+    Fork(u32, u32),
+}
+
+// The only leaf, at the start of the values
+const LEAF: u32 = 0;
+
+// What to do with the result of an application
+enum Cont {
+    // It is `a c` in `a c (b c)`: compute `b c` next
+    SecondHalf(u32, u32),
+    // Apply this to it
+    ApplyLeft(u32),
+    // Apply it to this
+    ApplyTo(u32),
+}
+
+pub fn compile_ops(expr: &Expr) -> Vec<Op> {
+    enum Task<'a> {
+        Visit(&'a Expr),
+        Emit(Op),
+    }
+    let mut ops = Vec::new();
+    let mut tasks = vec![Task::Visit(expr)];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Visit(expr) => {
+                ops.push(Op::Leaf);
+                for c in expr.children.iter().rev() {
+                    tasks.extend([Task::Emit(Op::Apply), Task::Visit(c)]);
+                }
+            }
+            Task::Emit(op) => ops.push(op),
+        }
+    }
+    ops
+}
+
+pub struct Machine {
+    code: Vec<Op>,
+    values: Vec<Value>,
+    stack: Vec<u32>,
+    // Number of rules applied so far
+    steps: u64,
+}
+
+impl Machine {
+    pub fn from_expr(expr: &Expr) -> Self {
+        Self {code: compile_ops(expr), values: vec![Value::Leaf], stack: Vec::new(), steps: 0}
+    }
+
+    pub fn step_count(&self) -> u64 {
+        self.steps
+    }
+
+    fn alloc(&mut self, value: Value) -> u32 {
+        self.values.push(value);
+        (self.values.len() - 1) as u32
+    }
+
+    fn apply(&mut self, f: u32, x: u32) -> u32 {
+        enum State {
+            Apply(u32, u32),
+            Return(u32),
+        }
+        let mut conts = Vec::new();
+        let mut state = State::Apply(f, x);
+        loop {
+            state = match state {
+                State::Apply(f, x) => match self.values[f as usize] {
+                    Value::Leaf => State::Return(self.alloc(Value::Stem(x))),
+                    Value::Stem(a) => State::Return(self.alloc(Value::Fork(a, x))),
+                    Value::Fork(p, b) => {
+                        self.steps += 1;
+                        match (self.values[p as usize], self.values[x as usize]) {
+                            (Value::Leaf, _) => State::Return(b),
+                            (Value::Stem(a), _) => {
+                                conts.push(Cont::SecondHalf(b, x));
+                                State::Apply(a, x)
+                            }
+                            (Value::Fork(w, _), Value::Leaf) => State::Return(w),
+                            (Value::Fork(_, x), Value::Stem(u)) => State::Apply(x, u),
+                            (Value::Fork(..), Value::Fork(u, v)) => {
+                                conts.push(Cont::ApplyTo(v));
+                                State::Apply(b, u)
+                            }
+                        }
+                    }
+                },
+                State::Return(result) => match conts.pop() {
+                    None => return result,
+                    Some(Cont::SecondHalf(b, x)) => {
+                        conts.push(Cont::ApplyLeft(result));
+                        State::Apply(b, x)
+                    }
+                    Some(Cont::ApplyLeft(f)) => State::Apply(f, result),
+                    Some(Cont::ApplyTo(x)) => State::Apply(result, x),
+                },
+            }
+        }
+    }
+
+    // Run the code until the stack holds the normal form of the tree
+    pub fn eval(&mut self) {
+        let code = std::mem::take(&mut self.code);
+        for op in &code {
+            match op {
+                Op::Leaf => self.stack.push(LEAF),
+                Op::Apply => {
+                    let x = self.stack.pop().unwrap();
+                    let f = self.stack.pop().unwrap();
+                    let result = self.apply(f, x);
+                    self.stack.push(result);
+                }
+            }
+        }
+        self.code = code;
+    }
+
+    // Read back the value on top of the stack, or None if nothing was evaluated
+    // yet
+    pub fn readback(&self) -> Option<Expr> {
+        enum Task {
+            Visit(u32),
+            // Pop this many trees from the results and make them the children
+            // of a new node
+            Build(usize),
+        }
+        let mut tasks = vec![Task::Visit(*self.stack.last()?)];
+        let mut results: Vec<Expr> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(index) => match self.values[index as usize] {
+                    Value::Leaf => results.push(Expr::leaf()),
+                    Value::Stem(a) => tasks.extend([Task::Build(1), Task::Visit(a)]),
+                    Value::Fork(a, b) => tasks.extend([Task::Build(2), Task::Visit(b), Task::Visit(a)]),
+                },
+                Task::Build(n) => {
+                    let children = results.split_off(results.len() - n);
+                    results.push(Expr::new(children));
+                }
+            }
+        }
+        results.pop()
+    }
+}
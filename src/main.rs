@@ -1,36 +1,195 @@
-
 // https://treecalcul.us/live/?example=demo-program-optimization
 // https://treecalcul.us/live/?example=demo-fusion
 // https://treecalcul.us/live/?example=bench
 // https://treecalcul.us/live/?example=demo-evaluator
 
-mod agent;
-mod code;
-mod compiler;
-mod containers;
-mod expr;
-mod global;
-mod parse;
-mod rules;
-mod test;
-mod vm;
-
 use std::env;
 use std::fs;
 use std::fs::OpenOptions;
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::OnceLock;
 
-use crate::compiler::*;
-use crate::vm::*;
+use tc_inet_rust::binary;
+use tc_inet_rust::codec::*;
+use tc_inet_rust::compiler::*;
+use tc_inet_rust::debugger::*;
+use tc_inet_rust::explain::*;
+use tc_inet_rust::format::*;
+use tc_inet_rust::lambda::*;
+use tc_inet_rust::machine::*;
+use tc_inet_rust::memo::*;
+use tc_inet_rust::notation::*;
+use tc_inet_rust::parse;
+use tc_inet_rust::pretty::*;
+use tc_inet_rust::reduce::*;
+use tc_inet_rust::trace::*;
+use tc_inet_rust::vm::*;
 
 fn print_help(prog_name: &str) {
     println!("USAGE: {} filename [-c/--compile]", prog_name);
     println!("Reads a tree from `filename`, and evaluates it.\n");
     println!("Flags:");
     println!("-c/--compile    Compile the tree into a .c file instead of interpreting it");
+    println!("--abstraction=naive|constant|eta");
+    println!("                Bracket abstraction strategy used to remove lambdas (default: eta)");
+    println!("--include=dir   Also look for imported files in `dir` (can be repeated)");
+    println!("--input=source|ternary|binary");
+    println!("                Notation of `filename` (default: source)");
+    println!("--output=t|delta|ternary");
+    println!("                Notation of the printed result (default: t)");
+    println!("--engine=inet|reference|memo|machine");
+    println!("                Evaluator to use: the interaction net VM, the slow reference reducer");
+    println!("                that rewrites trees directly, the evaluator that caches every");
+    println!("                application on hash-consed trees, or the stack machine (default: inet)");
+    println!("--max-interactions=N");
+    println!("                With the inet engine, give up after N interactions");
+    println!("--max-agents=N  With the inet engine, give up when more than N agents are alive");
+    println!("--progress=N    With the inet engine, report progress every N interactions");
+    println!("--stats[=table|json]");
+    println!("                With the inet engine, print the number of interactions of every rule,");
+    println!("                agents allocated and freed, peak live agents and active pairs, and time.");
+    println!("                With =json, the JSON object is written to stderr, on a line of its own");
+    println!("--trace=file    With the inet engine, write every interaction to `file`");
+    println!("--trace-format=jsonl|chrome");
+    println!("                Format of --trace: JSON Lines, or Chrome trace events (default: jsonl)");
+    println!("--dot=file      With the inet engine, write the net after evaluation to `file` as a");
+    println!("                Graphviz graph");
+    println!("--dot-every=N   With --dot, also write the net before evaluation and every N");
+    println!("                interactions, to `file` with the number of interactions added to its name");
+    println!("--debug         With the inet engine, step through the evaluation interactively, with");
+    println!("                breakpoints and reverse stepping (type help for the commands)");
+    println!("--explain       Print every rule applied by the reference reducer, and the tree after it");
+    println!("--pretty        Print the result on several lines, with the fewest parentheses");
+    println!("--width=N       Line width for --pretty and --fmt (default: 80)");
+    println!("--max-depth=N   With --pretty or --explain, print subtrees deeper than N as `…(size M)`");
+    println!("--fmt           Rewrite `filename` in canonical form instead of evaluating it");
+    println!("--save=file     Save the tree in binary format to `file` instead of evaluating it");
+    println!("--decode=TYPE   Print the result as a value of type TYPE instead of a tree, where TYPE");
+    println!("                is tree, bool, nat, int, bytes, string, list(TYPE), pair(TYPE, TYPE)");
+    println!("                or option(TYPE)");
     println!("-h/--help");
 }
 
+// Find the value of a `--name=value` flag
+fn flag_value<'a>(long_flags: &'a [String], name: &str) -> Option<&'a str> {
+    long_flags.iter().find_map(|f| f.strip_prefix(name)?.strip_prefix("="))
+}
+
+fn parse_number(value: &str) -> usize {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("Expected a number: {}", value);
+        process::exit(1);
+    })
+}
+
+fn format_progress(progress: &Progress) -> String {
+    format!("{} interactions, {} live agents, {} active pairs",
+        progress.interactions, progress.live_agents, progress.active_pairs)
+}
+
+// Write the net of `vm` as a Graphviz graph to `path`
+fn write_dot(vm: &VM, path: &Path) {
+    fs::write(path, vm.to_dot())
+        .unwrap_or_else(|_| panic!("Should be able to write to file: {}", path.display()));
+}
+
+// The file of the snapshot after `interactions` interactions, like
+// `net-000100.dot` for `net.dot`
+fn snapshot_path(path: &Path, interactions: u64) -> PathBuf {
+    let stem = path.file_stem().map_or("net".into(), |stem| stem.to_string_lossy());
+    let extension = path.extension().map_or("dot".into(), |extension| extension.to_string_lossy());
+    path.with_file_name(format!("{}-{:06}.{}", stem, interactions, extension))
+}
+
+// Evaluate like `VM::eval_with_limits`, writing a snapshot of the net before
+// and after every `interval` interactions
+fn eval_with_snapshots(vm: &mut VM, max_interactions: u64, max_agents: usize, interval: u64, path: &Path) -> EvalStatus {
+    write_dot(vm, &snapshot_path(path, vm.interaction_count()));
+    let mut fuel = max_interactions;
+    loop {
+        let before = vm.interaction_count();
+        let chunk = interval.min(fuel);
+        let status = vm.eval_with_limits(chunk, max_agents);
+        if vm.interaction_count() > before {
+            write_dot(vm, &snapshot_path(path, vm.interaction_count()));
+        }
+        // The fuel of a chunk is only left over when it stops for another
+        // reason, so the budget is the same as in a single call
+        fuel -= chunk;
+        if status != EvalStatus::OutOfFuel || fuel == 0 {
+            return status;
+        }
+    }
+}
+
+#[cfg(unix)]
+const SIGINT: i32 = 2;
+#[cfg(unix)]
+const SIG_ERR: usize = usize::MAX;
+
+#[cfg(unix)]
+extern "C" {
+    // The handlers are `sighandler_t`, the address of a function or one of
+    // `SIG_DFL`, `SIG_IGN` and `SIG_ERR`
+    fn signal(signum: i32, handler: usize) -> usize;
+    fn _exit(status: i32) -> !;
+}
+
+// Puts back the SIGINT handler that was there before `cancel_on_interrupt`
+// when it is dropped
+struct InterruptGuard {
+    #[cfg(unix)]
+    previous: Option<usize>,
+}
+
+#[cfg(unix)]
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous {
+            // SAFETY: `previous` was returned by `signal` for SIGINT, so it is
+            // a handler that SIGINT had before
+            unsafe {
+                signal(SIGINT, previous);
+            }
+        }
+    }
+}
+
+// Cancel `token` when the process receives SIGINT (Ctrl-C), instead of being
+// killed, until the guard is dropped. A second Ctrl-C, when the evaluation did
+// not stop after the first one, exits at once
+#[cfg(unix)]
+fn cancel_on_interrupt(token: CancelToken) -> InterruptGuard {
+    static TOKEN: OnceLock<CancelToken> = OnceLock::new();
+    extern "C" fn handle_interrupt(_: i32) {
+        if let Some(token) = TOKEN.get() {
+            if token.is_cancelled() {
+                // SAFETY: `_exit` is async-signal-safe, unlike `process::exit`
+                unsafe {
+                    _exit(130);
+                }
+            }
+            token.cancel();
+        }
+    }
+    if TOKEN.set(token).is_err() {
+        return InterruptGuard {previous: None};
+    }
+    // SAFETY: `handle_interrupt` has the signature of a signal handler, and
+    // only does atomic loads and stores and calls `_exit`, which are all
+    // async-signal-safe. `TOKEN` is set before it is installed, and never
+    // changes after
+    let previous = unsafe { signal(SIGINT, handle_interrupt as extern "C" fn(i32) as usize) };
+    InterruptGuard {previous: (previous != SIG_ERR).then_some(previous)}
+}
+
+#[cfg(not(unix))]
+fn cancel_on_interrupt(_token: CancelToken) -> InterruptGuard {
+    InterruptGuard {}
+}
+
 // Invocation: tc filename [--interpret | --compile]
 fn main () {
     // Read command-line args
@@ -38,13 +197,12 @@ fn main () {
     let mut short_flags = Vec::new();
     let mut long_flags = Vec::new();
     let mut filename= None;
-    for i in 1..args.len() {
-        let arg = &args[i];
-        match arg.strip_prefix("-") {
-            Some(f) => short_flags.push(f.to_string()),
+    for arg in args.iter().skip(1) {
+        match arg.strip_prefix("--") {
+            Some(f) => long_flags.push(f.to_string()),
             None => {
-                match arg.strip_prefix("--") {
-                    Some(f) => long_flags.push(f.to_string()),
+                match arg.strip_prefix("-") {
+                    Some(f) => short_flags.push(f.to_string()),
                     None => {
                         if filename.is_none() {
                             filename = Some(arg.to_string());
@@ -55,35 +213,261 @@ fn main () {
         }
     }
 
-    if short_flags.contains(&"h".to_string()) || long_flags.contains(&"help".to_string()) || filename.is_none() {
+    if short_flags.contains(&"h".to_string()) || long_flags.contains(&"help".to_string()) {
         print_help(&args[0]);
+        return;
+    }
+    let filename_str = match filename {
+        Some(f) => f,
+        None => {
+            print_help(&args[0]);
+            return;
+        }
+    };
+
+    let mut options = parse::ParseOptions::default();
+    if let Some(name) = flag_value(&long_flags, "abstraction") {
+        options.strategy = Strategy::from_name(name).unwrap_or_else(|| {
+            eprintln!("Unknown abstraction strategy: {}", name);
+            process::exit(1);
+        });
+    }
+    options.include_dirs = long_flags.iter()
+        .filter_map(|f| f.strip_prefix("include="))
+        .map(PathBuf::from)
+        .collect();
+
+    let output = match flag_value(&long_flags, "output") {
+        None => Notation::default(),
+        Some(name) => Notation::from_name(name).unwrap_or_else(|| {
+            eprintln!("Unknown output notation: {}", name);
+            process::exit(1);
+        }),
+    };
+
+    let mut pretty = PrettyOptions::default();
+    if let Some(width) = flag_value(&long_flags, "width") {
+        pretty.width = parse_number(width);
+    }
+    pretty.max_depth = flag_value(&long_flags, "max-depth").map(parse_number);
+
+    if long_flags.contains(&"fmt".to_string()) {
+        let src = fs::read_to_string(&filename_str)
+            .unwrap_or_else(|err| {
+                eprintln!("Could not read {}: {}", filename_str, err);
+                process::exit(1);
+            });
+        match format_source(&src, &pretty) {
+            Ok(formatted) => {
+                if formatted != src {
+                    fs::write(&filename_str, formatted)
+                        .unwrap_or_else(|_| panic!("Should be able to write to file: {}", &filename_str));
+                }
+            }
+            Err(err) => {
+                eprintln!("{}:{}", filename_str, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Explanations come from the reference reducer
+    let engine = if long_flags.contains(&"explain".to_string()) {
+        "reference"
+    } else {
+        flag_value(&long_flags, "engine").unwrap_or("inet")
+    };
+    if !["inet", "reference", "memo", "machine"].contains(&engine) {
+        eprintln!("Unknown engine: {}", engine);
+        process::exit(1);
+    }
+
+    let stats = if long_flags.contains(&"stats".to_string()) {
+        Some("table")
     } else {
-        // Read tree
-        let filename_str = filename.unwrap();
-        let tree_str = fs::read_to_string(&filename_str).expect(&format!("File should be readable: {}", &filename_str));
-        let expr = parse::parse_tree(&tree_str).unwrap();
-        println!("Size of tree: {}", expr.get_size());
-        if short_flags.contains(&"c".to_string()) || long_flags.contains(&"compile".to_string()) {
-            // Compile
-            let filename_c = filename_str.clone() + ".c";
-            let runtime_c = "src/runtime/runtime.c";
-            let runtime_str = fs::read_to_string(runtime_c).expect("Should be able to read src/runtime/runtime.c");
-            let code_str = compile(&expr);
-
-            let mut file = OpenOptions::new()
-                .create(true)
-                .truncate(true)
-                .write(true)
-                .open(&filename_c)
-                .unwrap();
-            file.write(runtime_str.as_bytes()).expect(&format!("Should be able to write to file: {}",&filename_c));
-            file.write(code_str.as_bytes()).expect(&format!("Should be able to write to file: {}",&filename_c));
-        } else {
-            // Interpret
-            let mut vm = VM::from_expr(expr);
-            vm.eval();
-            let result = vm.readback().to_string();
-            println!("{}", result);
+        flag_value(&long_flags, "stats")
+    };
+    if stats.is_some_and(|format| format != "table" && format != "json") {
+        eprintln!("Unknown stats format: {}", stats.unwrap());
+        process::exit(1);
+    }
+
+    let trace_format = match flag_value(&long_flags, "trace-format") {
+        None => TraceFormat::JsonLines,
+        Some(name) => TraceFormat::from_name(name).unwrap_or_else(|| {
+            eprintln!("Unknown trace format: {}", name);
+            process::exit(1);
+        }),
+    };
+
+    let decode = flag_value(&long_flags, "decode").map(|name| {
+        Type::from_name(name).unwrap_or_else(|| {
+            eprintln!("Unknown type: {}", name);
+            process::exit(1);
+        })
+    });
+
+    // Read tree
+    let input = flag_value(&long_flags, "input").unwrap_or("source");
+    let parsed = if input == "binary" {
+        let file = fs::read(&filename_str)
+            .unwrap_or_else(|err| {
+                eprintln!("Could not read {}: {}", filename_str, err);
+                process::exit(1);
+            });
+        binary::read_expr(&mut file.as_slice()).map_err(|err| err.to_string())
+    } else {
+        let tree_str = fs::read_to_string(&filename_str)
+            .unwrap_or_else(|err| {
+                eprintln!("Could not read {}: {}", filename_str, err);
+                process::exit(1);
+            });
+        match input {
+            "source" => parse::parse_tree(&tree_str, Some(Path::new(&filename_str)), &options),
+            "ternary" => parse_ternary(&tree_str),
+            name => {
+                eprintln!("Unknown input notation: {}", name);
+                process::exit(1);
+            }
+        }.map_err(|err| err.to_string())
+    };
+    let expr = match parsed {
+        Ok(expr) => expr,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    println!("Size of tree: {}", expr.get_size());
+    if let Some(filename_bin) = flag_value(&long_flags, "save") {
+        let file = fs::File::create(filename_bin)
+            .unwrap_or_else(|_| panic!("Should be able to write to file: {}", filename_bin));
+        let mut writer = BufWriter::new(file);
+        binary::write_expr(&expr, &mut writer)
+            .and_then(|_| writer.flush())
+            .unwrap_or_else(|_| panic!("Should be able to write to file: {}", filename_bin));
+    } else if short_flags.contains(&"c".to_string()) || long_flags.contains(&"compile".to_string()) {
+        // Compile
+        let filename_c = filename_str.clone() + ".c";
+        let runtime_c = "src/runtime/runtime.c";
+        let runtime_str = fs::read_to_string(runtime_c).expect("Should be able to read src/runtime/runtime.c");
+        let code_str = compile(&expr);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&filename_c)
+            .unwrap();
+        file.write_all(runtime_str.as_bytes())
+            .unwrap_or_else(|_| panic!("Should be able to write to file: {}", &filename_c));
+        file.write_all(code_str.as_bytes())
+            .unwrap_or_else(|_| panic!("Should be able to write to file: {}", &filename_c));
+    } else {
+        // Interpret
+        let result = match engine {
+            "inet" => {
+                let mut vm = VM::from_expr(expr);
+                if long_flags.contains(&"debug".to_string()) {
+                    let mut debugger = Debugger::new(vm);
+                    debugger.repl(std::io::stdin().lock(), &mut std::io::stdout().lock())
+                        .expect("Should be able to use stdin and stdout");
+                    return;
+                }
+                let max_interactions = flag_value(&long_flags, "max-interactions").map_or(u64::MAX, |n| parse_number(n) as u64);
+                let max_agents = flag_value(&long_flags, "max-agents").map_or(usize::MAX, parse_number);
+                let token = CancelToken::new();
+                let interrupt = cancel_on_interrupt(token.clone());
+                vm.set_cancel_token(token);
+                if let Some(interval) = flag_value(&long_flags, "progress") {
+                    vm.set_progress_callback(parse_number(interval) as u64, |progress| {
+                        eprintln!("Progress: {}", format_progress(progress));
+                    });
+                }
+                if let Some(filename_trace) = flag_value(&long_flags, "trace") {
+                    let file = fs::File::create(filename_trace)
+                        .unwrap_or_else(|_| panic!("Should be able to write to file: {}", filename_trace));
+                    vm.set_tracer(Some(Tracer::new(BufWriter::new(file), trace_format)));
+                }
+                let dot = flag_value(&long_flags, "dot").map(Path::new);
+                let status = match (dot, flag_value(&long_flags, "dot-every")) {
+                    (Some(path), Some(interval)) => {
+                        let interval = (parse_number(interval) as u64).max(1);
+                        eval_with_snapshots(&mut vm, max_interactions, max_agents, interval, path)
+                    }
+                    _ => vm.eval_with_limits(max_interactions, max_agents),
+                };
+                drop(interrupt);
+                if let Some(path) = dot {
+                    write_dot(&vm, path);
+                }
+                if let Some(tracer) = vm.set_tracer(None) {
+                    if let Err(err) = tracer.finish() {
+                        eprintln!("Could not write the trace: {}", err);
+                    }
+                }
+                match stats {
+                    Some("json") => eprintln!("{}", vm.stats().to_json()),
+                    Some(_) => println!("{}", vm.stats()),
+                    None => {}
+                }
+                match status {
+                    EvalStatus::Finished => {}
+                    EvalStatus::Cancelled => {
+                        eprintln!("Interrupted: {}", format_progress(&vm.progress()));
+                        process::exit(130);
+                    }
+                    EvalStatus::OutOfFuel => {
+                        eprintln!("Stopped after {} interactions", max_interactions);
+                        process::exit(2);
+                    }
+                    EvalStatus::OutOfMemory => {
+                        eprintln!("Stopped with more than {} live agents", max_agents);
+                        process::exit(2);
+                    }
+                }
+                vm.readback()
+            }
+            "memo" => {
+                let mut evaluator = MemoEvaluator::new();
+                let result = evaluator.eval_expr(&expr);
+                println!("Applications: {}", evaluator.stats);
+                result
+            }
+            "machine" => {
+                let mut machine = Machine::from_expr(&expr);
+                machine.eval();
+                println!("Steps: {}", machine.step_count());
+                machine.readback().expect("The machine has evaluated the tree")
+            }
+            _ if long_flags.contains(&"explain".to_string()) => {
+                let options = ExplainOptions {max_depth: pretty.max_depth};
+                explain(expr, &options, &mut std::io::stdout().lock())
+                    .expect("Should be able to write to stdout")
+            }
+            _ => reduce(expr),
+        };
+        if let Some(ty) = decode {
+            match Value::decode(&result, &ty) {
+                Some(value) => println!("{}", value),
+                None => {
+                    eprintln!("The result is not a value of the given type: {}", result);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        if long_flags.contains(&"pretty".to_string()) {
+            println!("{}", pretty_expr(&result, &pretty));
+            return;
+        }
+        match format_expr(&result, output) {
+            Ok(result) => println!("{}", result),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
         }
     }
 }
@@ -0,0 +1,198 @@
+// An evaluator on hash-consed trees, which remembers the result of every
+// application of a fork to an argument. Since equal trees have equal ids in the
+// arena, an application that was already computed is found in the cache in
+// constant time, however big the trees are.
+//
+// Arguments are reduced to normal form before they are applied. Unlike the
+// reference reducer, it does not terminate on a program that drops an argument
+// that has no normal form. The rules are the same as the VM's:
+//   t t b c             = b
+//   t (t a) b c         = a c (b c)
+//   t (t w x) y t       = w
+//   t (t w x) y (t u)   = x u
+//   t (t w x) y (t u v) = y u v
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::arena::*;
+use crate::expr::*;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoStats {
+    // Applications of a fork whose result was in the cache
+    pub hits: u64,
+    // Applications of a fork that had to be computed, so also the number of
+    // rules applied
+    pub misses: u64,
+}
+
+impl MemoStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+impl fmt::Display for MemoStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} cache hits, {} misses ({:.1}% hit rate)", self.hits, self.misses, self.hit_rate() * 100.0)
+    }
+}
+
+// What to do with the result of an application
+enum Cont {
+    // Remember it as the result of applying `f` to `x`
+    Cache(NodeId, NodeId),
+    // It is `a c` in `a c (b c)`: compute `b c` next
+    SecondHalf(NodeId, NodeId),
+    // Apply this to it
+    ApplyLeft(NodeId),
+    // Apply it to this
+    ApplyTo(NodeId),
+}
+
+pub struct MemoEvaluator {
+    pub arena: Arena,
+    // Results of applying a fork to an argument, both in normal form
+    cache: HashMap<(NodeId, NodeId), NodeId>,
+    // Normal forms of the trees evaluated so far
+    normal_forms: HashMap<NodeId, NodeId>,
+    pub stats: MemoStats,
+}
+
+impl Default for MemoEvaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoEvaluator {
+    pub fn new() -> Self {
+        Self {
+            arena: Arena::new(),
+            cache: HashMap::new(),
+            normal_forms: HashMap::new(),
+            stats: MemoStats::default(),
+        }
+    }
+
+    // Number of applications whose result is remembered
+    pub fn cache_size(&self) -> usize {
+        self.cache.len()
+    }
+
+    // Apply `f` to `x`, both in normal form, and return the normal form of the
+    // result
+    pub fn apply(&mut self, f: NodeId, x: NodeId) -> NodeId {
+        enum State {
+            Apply(NodeId, NodeId),
+            Return(NodeId),
+        }
+        let mut conts = Vec::new();
+        let mut state = State::Apply(f, x);
+        loop {
+            state = match state {
+                State::Apply(f, x) => match self.arena.node(f) {
+                    Node::Leaf | Node::Stem(_) => State::Return(self.arena.app(f, x)),
+                    Node::Fork(p, b) => {
+                        if let Some(result) = self.cache.get(&(f, x)) {
+                            self.stats.hits += 1;
+                            State::Return(*result)
+                        } else {
+                            self.stats.misses += 1;
+                            conts.push(Cont::Cache(f, x));
+                            match (self.arena.node(p), self.arena.node(x)) {
+                                (Node::Leaf, _) => State::Return(b),
+                                (Node::Stem(a), _) => {
+                                    conts.push(Cont::SecondHalf(b, x));
+                                    State::Apply(a, x)
+                                }
+                                (Node::Fork(w, _), Node::Leaf) => State::Return(w),
+                                (Node::Fork(_, x), Node::Stem(u)) => State::Apply(x, u),
+                                (Node::Fork(..), Node::Fork(u, v)) => {
+                                    conts.push(Cont::ApplyTo(v));
+                                    State::Apply(b, u)
+                                }
+                                _ => unreachable!("applications are only made of normal forms"),
+                            }
+                        }
+                    }
+                    Node::App(..) => unreachable!("applications are only made of normal forms"),
+                },
+                State::Return(result) => match conts.pop() {
+                    None => return result,
+                    Some(Cont::Cache(f, x)) => {
+                        self.cache.insert((f, x), result);
+                        State::Return(result)
+                    }
+                    Some(Cont::SecondHalf(b, x)) => {
+                        conts.push(Cont::ApplyLeft(result));
+                        State::Apply(b, x)
+                    }
+                    Some(Cont::ApplyLeft(f)) => State::Apply(f, result),
+                    Some(Cont::ApplyTo(x)) => State::Apply(result, x),
+                },
+            }
+        }
+    }
+
+    // Return the normal form of the tree at `id`
+    pub fn eval(&mut self, id: NodeId) -> NodeId {
+        enum Task {
+            Visit(NodeId),
+            // Pop the normal forms of the children of the node from the
+            // results, and compute the normal form of the node
+            Build(NodeId),
+        }
+        let mut tasks = vec![Task::Visit(id)];
+        let mut results = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(id) => {
+                    if let Some(normal_form) = self.normal_forms.get(&id) {
+                        results.push(*normal_form);
+                        continue;
+                    }
+                    tasks.push(Task::Build(id));
+                    match self.arena.node(id) {
+                        Node::Leaf => {}
+                        Node::Stem(a) => tasks.push(Task::Visit(a)),
+                        Node::Fork(a, b) | Node::App(a, b) => tasks.extend([Task::Visit(b), Task::Visit(a)]),
+                    }
+                }
+                Task::Build(id) => {
+                    let normal_form = match self.arena.node(id) {
+                        Node::Leaf => id,
+                        Node::Stem(_) => {
+                            let a = results.pop().unwrap();
+                            self.arena.stem(a)
+                        }
+                        node => {
+                            let b = results.pop().unwrap();
+                            let a = results.pop().unwrap();
+                            if let Node::App(..) = node {
+                                self.apply(a, b)
+                            } else {
+                                self.arena.fork(a, b)
+                            }
+                        }
+                    };
+                    self.normal_forms.insert(id, normal_form);
+                    results.push(normal_form);
+                }
+            }
+        }
+        results.pop().unwrap()
+    }
+
+    pub fn eval_expr(&mut self, expr: &Expr) -> Expr {
+        let id = self.arena.intern_expr(expr);
+        let normal_form = self.eval(id);
+        self.arena.to_expr(normal_form)
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::arena::*;
+use crate::lambda::*;
+use crate::lexer::*;
+use crate::parse::*;
+
+pub const MODULE_EXTENSION: &str = "tc";
+
+// The definitions of an imported file, already expanded
+pub struct Module {
+    pub defs: HashMap<String, NodeId>,
+}
+
+// Loads imported files. Every file is parsed once, even if it is imported by
+// several other files
+pub struct Loader {
+    pub strategy: Strategy,
+    // Holds the trees of every definition in every file
+    pub arena: Arena,
+    include_dirs: Vec<PathBuf>,
+    modules: HashMap<PathBuf, Rc<Module>>,
+    // Files that are being parsed, the importing file before the imported one
+    loading: Vec<PathBuf>,
+}
+
+impl Loader {
+    pub fn new(options: &ParseOptions) -> Self {
+        Self {
+            strategy: options.strategy,
+            arena: Arena::new(),
+            include_dirs: options.include_dirs.clone(),
+            modules: HashMap::new(),
+            loading: Vec::new(),
+        }
+    }
+
+    // Mark `path` as being parsed, so importing it again is reported as a cycle
+    pub fn enter(&mut self, path: &Path) {
+        self.loading.push(path.canonicalize().unwrap_or(path.to_path_buf()));
+    }
+
+    // Look for `import` next to the importing file, then in the include
+    // directories
+    fn resolve(&self, import: &str, from: Option<&Path>) -> Option<PathBuf> {
+        let mut file = PathBuf::from(import);
+        if file.extension().is_none() {
+            file.set_extension(MODULE_EXTENSION);
+        }
+        let base = from.and_then(|p| p.parent()).unwrap_or(Path::new(""));
+        std::iter::once(base.to_path_buf())
+            .chain(self.include_dirs.iter().cloned())
+            .map(|dir| dir.join(&file))
+            .find(|path| path.is_file())
+    }
+
+    // Load the module imported as `import` from the file `from`. `span` is
+    // the position of the import, used for error reporting
+    pub fn load(&mut self, import: &str, from: Option<&Path>, span: Span) -> Result<Rc<Module>, ParseError> {
+        let path = self.resolve(import, from).ok_or_else(|| {
+            ParseError::new(span, format!("missing module `{}`", import), "an existing file")
+        })?;
+        let canonical = path.canonicalize().unwrap_or(path.clone());
+        if let Some(module) = self.modules.get(&canonical) {
+            return Ok(module.clone());
+        }
+        if let Some(start) = self.loading.iter().position(|p| *p == canonical) {
+            let cycle: Vec<String> = self.loading[start..].iter()
+                .chain(std::iter::once(&canonical))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(ParseError::new(span,
+                format!("import cycle {}", cycle.join(" -> ")), "an import that is not already being loaded"));
+        }
+
+        let src = fs::read_to_string(&path).map_err(|err| {
+            ParseError::new(span, format!("unreadable module `{}` ({})", import, err), "a readable file")
+        })?;
+        self.loading.push(canonical.clone());
+        let module = parse_module(&src, &path, self);
+        self.loading.pop();
+
+        let module = Rc::new(module?);
+        self.modules.insert(canonical, module.clone());
+        Ok(module)
+    }
+}
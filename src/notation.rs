@@ -0,0 +1,119 @@
+// Other ways of writing trees down:
+// - Delta: like the `t` notation, but with `△` as the leaf, e.g. `△ (△ △) △`
+// - Ternary: every node in prefix order as a digit (0 = leaf, 1 = stem,
+//   2 = fork), e.g. `2100`. It can only describe trees in normal form
+
+use crate::expr::*;
+use crate::lexer::*;
+use crate::parse::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Notation {
+    #[default]
+    T,
+    Delta,
+    Ternary,
+}
+
+impl Notation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "t" => Some(Notation::T),
+            "delta" => Some(Notation::Delta),
+            "ternary" => Some(Notation::Ternary),
+            _ => None,
+        }
+    }
+}
+
+fn write_delta(expr: &Expr, str: &mut String) {
+    enum Task<'a> {
+        Node(&'a Expr),
+        Text(&'static str),
+    }
+    let mut tasks = vec![Task::Node(expr)];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Node(expr) => {
+                str.push('△');
+                for c in expr.children.iter().rev() {
+                    if c.children.is_empty() {
+                        tasks.push(Task::Node(c));
+                    } else {
+                        tasks.extend([Task::Text(")"), Task::Node(c), Task::Text("(")]);
+                    }
+                    tasks.push(Task::Text(" "));
+                }
+            }
+            Task::Text(text) => str.push_str(text),
+        }
+    }
+}
+
+fn write_ternary(expr: &Expr, str: &mut String) -> Result<(), String> {
+    let mut stack = vec![expr];
+    while let Some(expr) = stack.pop() {
+        match expr.children.len() {
+            0 => str.push('0'),
+            1 => str.push('1'),
+            2 => str.push('2'),
+            _ => return Err("an application cannot be written in ternary notation".to_string()),
+        }
+        stack.extend(expr.children.iter().rev());
+    }
+    Ok(())
+}
+
+// Write `expr` in the given notation. Fails if the notation cannot express it
+pub fn format_expr(expr: &Expr, notation: Notation) -> Result<String, String> {
+    let mut str = String::new();
+    match notation {
+        Notation::T => str = expr.to_string(),
+        Notation::Delta => write_delta(expr, &mut str),
+        Notation::Ternary => write_ternary(expr, &mut str)?,
+    }
+    Ok(str)
+}
+
+// Parse a tree in ternary notation. Whitespace between the digits is ignored
+pub fn parse_ternary(str: &str) -> Result<Expr, ParseError> {
+    let mut digits = Vec::new();
+    let mut span = Span {line: 1, column: 1};
+    // The number of subtrees still to be read
+    let mut missing = 1;
+    for c in str.chars() {
+        if c.is_whitespace() {
+            if c == '\n' {
+                span.line += 1;
+                span.column = 1;
+            } else {
+                span.column += 1;
+            }
+            continue;
+        }
+        let arity = match c {
+            '0' => 0,
+            '1' => 1,
+            '2' => 2,
+            c => return Err(ParseError::new(span, format!("`{}`", c), "`0`, `1` or `2`")),
+        };
+        if missing == 0 {
+            return Err(ParseError::new(span, format!("`{}`", c), "end of input after a complete tree"));
+        }
+        missing = missing - 1 + arity;
+        digits.push(arity);
+        span.column += 1;
+    }
+    if missing > 0 {
+        return Err(ParseError::new(span, "end of input".to_string(), "`0`, `1` or `2`"));
+    }
+
+    // Build the tree from the last digit to the first one, so the children of
+    // every node are already on the stack when it is reached
+    let mut stack = Vec::new();
+    for arity in digits.into_iter().rev() {
+        let children = stack.split_off(stack.len() - arity);
+        stack.push(Expr::new(children.into_iter().rev().collect()));
+    }
+    Ok(stack.pop().unwrap())
+}
@@ -1,40 +1,667 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::arena::*;
+use crate::codec::*;
 use crate::expr::*;
 use crate::global::*;
+use crate::lambda::*;
+use crate::lexer::*;
+use crate::module::*;
 
-pub fn parse_tree(str: &str) -> Option<Expr> {
-    crate::debug_log!("\n=== Parsing String into Expr ===\n");
-    let mut expr: Option<Expr> = None;
-    let mut stack: Vec<Option<Expr>> = Vec::new();
-    for c in str.chars() {
-        match c {
-            '(' => {
-                stack.push(expr.take());
-            }
-            ')' => {
-                if let Some(mut temp) = stack.pop().flatten() {
-                    if let Some(some_expr) = expr.take() {
-                        temp.children.push(some_expr)
-                    }
-                    expr = Some(temp);
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    // The file the error is in, if the source was read from a file
+    pub file: Option<PathBuf>,
+    pub span: Span,
+    pub found: String,
+    pub expected: String,
+}
+
+impl ParseError {
+    pub fn new(span: Span, found: String, expected: &str) -> Self {
+        Self {file: None, span, found, expected: expected.to_string()}
+    }
+
+    // Attribute the error to `file`, unless it already came from another one
+    fn in_file(mut self, file: Option<&Path>) -> Self {
+        if self.file.is_none() {
+            self.file = file.map(|f| f.to_path_buf());
+        }
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        write!(f, "{}: expected {}, found {}", self.span, self.expected, self.found)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    // How lambdas are translated to trees
+    pub strategy: Strategy,
+    // Directories searched for imported files that are not found next to the
+    // importing file
+    pub include_dirs: Vec<PathBuf>,
+}
+
+impl std::error::Error for ParseError {}
+
+// A `#` comment, without the `#`
+#[derive(Clone, Debug)]
+pub struct Comment {
+    pub span: Span,
+    pub text: String,
+}
+
+// An expression as it is written, before names are resolved
+#[derive(Debug)]
+pub enum Syntax {
+    Leaf,
+    // A variable or a definition
+    Name(Span, String),
+    // `module.name`, with the position of `name`
+    Qualified(Span, String, String),
+    // Decimal digits, as written
+    Number(String),
+    Str(Vec<u8>),
+    List(Vec<Syntax>),
+    // A function and its arguments. The function is never an application
+    App(Vec<Syntax>),
+    // Nested lambdas are merged, so `\x. \y. x` has the variables `x` and `y`
+    Lam(Vec<String>, Box<Syntax>),
+}
+
+impl Syntax {
+    // The application of the first part to the others, or the part itself if
+    // there is only one
+    fn app(mut parts: Vec<Syntax>) -> Syntax {
+        if parts.len() == 1 {
+            parts.pop().unwrap()
+        } else {
+            Syntax::App(parts)
+        }
+    }
+}
+
+impl Drop for Syntax {
+    fn drop(&mut self) {
+        // Like `Expr`, detach the descendants before they are dropped
+        let mut stack = Vec::new();
+        let take = |syntax: &mut Syntax, stack: &mut Vec<Syntax>| match syntax {
+            Syntax::List(parts) | Syntax::App(parts) => stack.append(parts),
+            Syntax::Lam(_, body) => stack.push(std::mem::replace(&mut **body, Syntax::Leaf)),
+            _ => {}
+        };
+        take(self, &mut stack);
+        while let Some(mut syntax) = stack.pop() {
+            take(&mut syntax, &mut stack);
+        }
+    }
+}
+
+// The module path of an import, as written
+#[derive(Clone, Debug)]
+pub enum ModulePath {
+    Str(Vec<u8>),
+    // Names separated by `/`
+    Names(Vec<String>),
+}
+
+impl ModulePath {
+    // The path to look the file up with
+    pub fn path(&self) -> String {
+        match self {
+            ModulePath::Str(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            ModulePath::Names(names) => names.join("/"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ItemKind {
+    // The module path, and the name after `as`
+    Import(ModulePath, Option<String>),
+    Definition(String, Syntax),
+    // The final expression of a program
+    Expr(Syntax),
+}
+
+#[derive(Debug)]
+pub struct Item {
+    pub kind: ItemKind,
+    // The positions of the first and the last token of the item
+    pub start: Span,
+    pub end: Span,
+}
+
+// A file as it is written, with its comments. Blank lines are found from the
+// positions of the items and the comments
+#[derive(Debug)]
+pub struct SourceFile {
+    pub items: Vec<Item>,
+    pub comments: Vec<Comment>,
+    // The position of the end of the input
+    pub end: Span,
+}
+
+// Builds the syntax of a file from its tokens
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // The position of the last token consumed
+    last_span: Span,
+    // The column of the name of the definition whose body is being parsed
+    definition_column: Option<usize>,
+    // Number of enclosing parentheses and brackets
+    nesting: usize,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Self {tokens, pos: 0, last_span: Span {line: 1, column: 1}, definition_column: None, nesting: 0}
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        // The last token is always `Eof`, so never move past it
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        self.last_span = token.span;
+        token
+    }
+
+    fn error(&self, expected: &str) -> ParseError {
+        let token = self.peek();
+        ParseError::new(token.span, token.kind.to_string(), expected)
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<Token, ParseError> {
+        if self.peek().kind == kind {
+            Ok(self.advance())
+        } else {
+            Err(self.error(&kind.to_string()))
+        }
+    }
+
+    // A name followed by `=` starts a new definition
+    fn at_definition(&self) -> bool {
+        matches!(self.peek().kind, TokenKind::Ident(_)) &&
+            self.tokens.get(self.pos + 1).is_some_and(|t| t.kind == TokenKind::Equals)
+    }
+
+    // The body of a definition ends at a line that starts no further right
+    // than the name of the definition, unless it is inside parentheses or
+    // brackets
+    fn at_body_end(&self) -> bool {
+        self.nesting == 0 && self.definition_column.is_some_and(|c| self.peek().span.column <= c)
+    }
+
+    fn starts_atom(&self) -> bool {
+        if self.at_body_end() {
+            return false;
+        }
+        match self.peek().kind {
+            TokenKind::Leaf | TokenKind::LParen | TokenKind::LBracket |
+            TokenKind::Number(_) | TokenKind::Str(_) => true,
+            TokenKind::Ident(_) => !self.at_definition(),
+            _ => false,
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, ParseError> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Ident(name) => Ok(name),
+            kind => Err(ParseError::new(token.span, kind.to_string(), "a name")),
+        }
+    }
+
+    // A `t`, a name, a qualified name or a literal
+    // qualified := module `.` name
+    fn parse_simple_atom(&mut self) -> Result<Syntax, ParseError> {
+        match self.peek().kind.clone() {
+            TokenKind::Leaf => {
+                self.advance();
+                Ok(Syntax::Leaf)
+            }
+            TokenKind::Ident(name) => {
+                let span = self.advance().span;
+                if self.peek().kind == TokenKind::Dot {
+                    self.advance();
+                    let span = self.peek().span;
+                    return Ok(Syntax::Qualified(span, name, self.expect_ident()?));
                 }
+                Ok(Syntax::Name(span, name))
+            }
+            TokenKind::Number(digits) => {
+                self.advance();
+                Ok(Syntax::Number(digits))
+            }
+            TokenKind::Str(bytes) => {
+                self.advance();
+                Ok(Syntax::Str(bytes))
             }
-            c => {
-                if !c.is_whitespace() {
-                    match expr {
-                        None => {
-                            expr = Some(Expr {
-                                children: Vec::new(),
-                            });
+            _ => Err(self.error("`t`, a name, a literal, `\\` or `(`")),
+        }
+    }
+
+    // The variables of a lambda, up to its `.`
+    fn parse_binders(&mut self) -> Result<Vec<String>, ParseError> {
+        self.expect(TokenKind::Lambda)?;
+        let mut vars = vec![self.expect_ident()?];
+        while self.peek().kind != TokenKind::Dot {
+            vars.push(self.expect_ident()?);
+        }
+        self.expect(TokenKind::Dot)?;
+        Ok(vars)
+    }
+
+    // expr := lambda | atom+ lambda?
+    // atom := simple | list | `(` expr `)`
+    // list := `[` (expr (`,` expr)* `,`?)? `]`
+    // lambda := `\` name+ `.` expr
+    // Application is left associative, and a lambda extends as far to the
+    // right as possible. The expressions that are not finished are kept on a
+    // stack instead of the call stack, so deeply nested sources can be parsed
+    fn parse_expr(&mut self) -> Result<Syntax, ParseError> {
+        enum Frame {
+            // The parts of an application so far
+            App(Vec<Syntax>),
+            // An application whose last part, a lambda, is being parsed
+            LastApp(Vec<Syntax>),
+            // The variables of a lambda whose body is being parsed
+            Lam(Vec<String>),
+            Paren,
+            // The items of a list so far
+            List(Vec<Syntax>),
+        }
+        enum State {
+            Expr,
+            Atom,
+            // An expression or an atom is finished
+            Done(Syntax),
+        }
+        let mut frames = Vec::new();
+        let mut state = State::Expr;
+        loop {
+            state = match state {
+                State::Expr if self.peek().kind == TokenKind::Lambda => {
+                    frames.push(Frame::Lam(self.parse_binders()?));
+                    State::Expr
+                }
+                State::Expr => {
+                    frames.push(Frame::App(Vec::new()));
+                    State::Atom
+                }
+                State::Atom => match self.peek().kind {
+                    TokenKind::LParen => {
+                        self.advance();
+                        self.nesting += 1;
+                        frames.push(Frame::Paren);
+                        State::Expr
+                    }
+                    TokenKind::LBracket => {
+                        self.advance();
+                        self.nesting += 1;
+                        if self.peek().kind == TokenKind::RBracket {
+                            self.advance();
+                            self.nesting -= 1;
+                            State::Done(Syntax::List(Vec::new()))
+                        } else {
+                            frames.push(Frame::List(Vec::new()));
+                            State::Expr
+                        }
+                    }
+                    _ => State::Done(self.parse_simple_atom()?),
+                },
+                State::Done(mut syntax) => match frames.pop() {
+                    None => return Ok(syntax),
+                    Some(Frame::App(mut parts)) => {
+                        // A parenthesized application at the head is merged
+                        if let (true, Syntax::App(head)) = (parts.is_empty(), &mut syntax) {
+                            parts = std::mem::take(head);
+                        } else {
+                            parts.push(syntax);
+                        }
+                        if self.starts_atom() {
+                            frames.push(Frame::App(parts));
+                            State::Atom
+                        } else if self.peek().kind == TokenKind::Lambda && !self.at_body_end() {
+                            frames.push(Frame::LastApp(parts));
+                            State::Expr
+                        } else {
+                            State::Done(Syntax::app(parts))
+                        }
+                    }
+                    Some(Frame::LastApp(mut parts)) => {
+                        parts.push(syntax);
+                        State::Done(Syntax::app(parts))
+                    }
+                    Some(Frame::Lam(mut vars)) => {
+                        if let Syntax::Lam(inner_vars, body) = &mut syntax {
+                            vars.append(inner_vars);
+                            syntax = std::mem::replace(&mut **body, Syntax::Leaf);
+                        }
+                        State::Done(Syntax::Lam(vars, Box::new(syntax)))
+                    }
+                    Some(Frame::Paren) => {
+                        self.expect(TokenKind::RParen)?;
+                        self.nesting -= 1;
+                        State::Done(syntax)
+                    }
+                    Some(Frame::List(mut items)) => {
+                        items.push(syntax);
+                        if self.peek().kind == TokenKind::Comma {
+                            self.advance();
+                        } else if self.peek().kind != TokenKind::RBracket {
+                            return Err(self.error("`,` or `]`"));
                         }
-                        Some(ref mut some_expr) => {
-                            some_expr.children.push(Expr {
-                                children: Vec::new(),
-                            });
+                        if self.peek().kind == TokenKind::RBracket {
+                            self.advance();
+                            self.nesting -= 1;
+                            State::Done(Syntax::List(items))
+                        } else {
+                            frames.push(Frame::List(items));
+                            State::Expr
                         }
                     }
+                },
+            };
+        }
+    }
+
+    // definition := name `=` expr
+    fn parse_definition(&mut self) -> Result<ItemKind, ParseError> {
+        let column = self.peek().span.column;
+        let name = self.expect_ident()?;
+        self.expect(TokenKind::Equals)?;
+        self.definition_column = Some(column);
+        let body = self.parse_expr();
+        self.definition_column = None;
+        Ok(ItemKind::Definition(name, body?))
+    }
+
+    // import := `import` (string | name (`/` name)*) (`as` name)?
+    fn parse_import(&mut self) -> Result<ItemKind, ParseError> {
+        self.expect(TokenKind::Import)?;
+        let path = match self.peek().kind.clone() {
+            TokenKind::Str(bytes) => {
+                self.advance();
+                ModulePath::Str(bytes)
+            }
+            TokenKind::Ident(_) => {
+                let mut names = vec![self.expect_ident()?];
+                while self.peek().kind == TokenKind::Slash {
+                    self.advance();
+                    names.push(self.expect_ident()?);
                 }
+                ModulePath::Names(names)
             }
+            _ => return Err(self.error("a module path")),
+        };
+        let alias = if self.peek().kind == TokenKind::Ident("as".to_string()) {
+            self.advance();
+            Some(self.expect_ident()?)
+        } else {
+            None
+        };
+        Ok(ItemKind::Import(path, alias))
+    }
+
+    fn parse_item(&mut self, parse: fn(&mut Self) -> Result<ItemKind, ParseError>) -> Result<Item, ParseError> {
+        let start = self.peek().span;
+        let kind = parse(self)?;
+        Ok(Item {kind, start, end: self.last_span})
+    }
+
+    // file := import* definition* expr?
+    // The final expression is only allowed in a program, and not together with
+    // a definition of `main`
+    fn parse_file(&mut self, is_program: bool) -> Result<Vec<Item>, ParseError> {
+        let mut items = Vec::new();
+        while self.peek().kind == TokenKind::Import {
+            items.push(self.parse_item(Self::parse_import)?);
+        }
+        while self.at_definition() {
+            items.push(self.parse_item(Self::parse_definition)?);
+        }
+        if self.peek().kind == TokenKind::Eof {
+            return Ok(items);
+        }
+        if !is_program {
+            return Err(self.error("a definition"));
+        }
+        if items.iter().any(|item| matches!(&item.kind, ItemKind::Definition(name, _) if name == "main")) {
+            return Err(self.error("end of input after the definition of `main`"));
+        }
+        items.push(self.parse_item(|parser| Ok(ItemKind::Expr(parser.parse_expr()?)))?);
+        self.expect(TokenKind::Eof)?;
+        Ok(items)
+    }
+}
+
+// Parse a file into its syntax, without resolving names or loading imports
+fn parse_syntax(str: &str, is_program: bool) -> Result<SourceFile, ParseError> {
+    let mut comments = Vec::new();
+    let mut tokens = Vec::new();
+    for token in Lexer::with_comments(str).tokenize()? {
+        match token.kind {
+            TokenKind::Comment(text) => comments.push(Comment {span: token.span, text}),
+            _ => tokens.push(token),
+        }
+    }
+    let mut parser = Parser::new(tokens);
+    let items = parser.parse_file(is_program)?;
+    Ok(SourceFile {items, comments, end: parser.peek().span})
+}
+
+// Parse the source of a program or a module into its syntax
+pub fn parse_source(str: &str) -> Result<SourceFile, ParseError> {
+    parse_syntax(str, true)
+}
+
+// Turns the syntax of a file into trees, loading the modules it imports
+struct Resolver<'a> {
+    // The file being parsed, if any. Imports are resolved relative to it
+    path: Option<&'a Path>,
+    loader: &'a mut Loader,
+    // Imported modules, by the name they are referred to with
+    modules: HashMap<String, Rc<Module>>,
+    // Definitions seen so far, already expanded
+    defs: HashMap<String, NodeId>,
+    // Variables bound by the enclosing lambdas, innermost last
+    scope: Vec<String>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(path: Option<&'a Path>, loader: &'a mut Loader) -> Self {
+        Self {path, loader, modules: HashMap::new(), defs: HashMap::new(), scope: Vec::new()}
+    }
+
+    fn name(&mut self, span: Span, name: &str) -> Result<Term, ParseError> {
+        if self.scope.iter().any(|var| var == name) {
+            return Ok(Term::Var(name.to_string()));
+        }
+        match (self.defs.get(name), name) {
+            (Some(id), _) => Ok(Term::Tree(*id)),
+            (None, "true") => Ok(Term::Tree(self.loader.arena.intern_expr(&encode_bool(true)))),
+            (None, "false") => Ok(Term::Tree(self.loader.arena.intern_expr(&encode_bool(false)))),
+            (None, _) => Err(ParseError::new(span,
+                format!("undefined name `{}`", name), "a name defined earlier")),
         }
     }
-    expr
+
+    fn qualified(&self, span: Span, module_name: &str, name: &str) -> Result<Term, ParseError> {
+        let module = self.modules.get(module_name).ok_or_else(|| ParseError::new(span,
+            format!("undefined module `{}`", module_name), "an imported module"))?;
+        match module.defs.get(name) {
+            Some(id) => Ok(Term::Tree(*id)),
+            None => Err(ParseError::new(span,
+                format!("undefined name `{}.{}`", module_name, name),
+                &format!("a name defined in `{}`", module_name))),
+        }
+    }
+
+    // Like the parser, this keeps the expressions that are not translated yet
+    // on its own stack
+    fn term(&mut self, syntax: &Syntax) -> Result<Term, ParseError> {
+        enum Task<'s> {
+            Translate(&'s Syntax),
+            // Apply the first of the last this many terms to the others
+            App(usize),
+            // Make a list of the last this many terms
+            List(usize),
+            // Wrap the last term in lambdas, and remove their variables from
+            // the scope
+            Lam(&'s [String]),
+        }
+        let mut tasks = vec![Task::Translate(syntax)];
+        let mut done = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Translate(Syntax::Leaf) => done.push(Term::Tree(self.loader.arena.leaf())),
+                Task::Translate(Syntax::Name(span, name)) => done.push(self.name(*span, name)?),
+                Task::Translate(Syntax::Qualified(span, module_name, name)) => {
+                    done.push(self.qualified(*span, module_name, name)?);
+                }
+                Task::Translate(Syntax::Number(digits)) => {
+                    // The lexer only produces decimal digits
+                    let nat = encode_nat_decimal(digits).unwrap();
+                    done.push(Term::Tree(self.loader.arena.intern_expr(&nat)));
+                }
+                Task::Translate(Syntax::Str(bytes)) => {
+                    done.push(Term::Tree(self.loader.arena.intern_expr(&encode_bytes(bytes))));
+                }
+                Task::Translate(Syntax::List(items)) => {
+                    tasks.push(Task::List(items.len()));
+                    tasks.extend(items.iter().rev().map(Task::Translate));
+                }
+                Task::Translate(Syntax::App(parts)) => {
+                    tasks.push(Task::App(parts.len()));
+                    tasks.extend(parts.iter().rev().map(Task::Translate));
+                }
+                Task::Translate(Syntax::Lam(vars, body)) => {
+                    self.scope.extend(vars.iter().cloned());
+                    tasks.push(Task::Lam(vars));
+                    tasks.push(Task::Translate(body));
+                }
+                // A list is a chain of forks ending in a leaf: [a, b] = t a (t b t)
+                Task::List(len) => {
+                    let leaf = self.loader.arena.leaf();
+                    let items = done.split_off(done.len() - len);
+                    let list = items.into_iter().rev().fold(Term::Tree(leaf), |list, item| {
+                        Term::app(Term::app(Term::Tree(leaf), item), list)
+                    });
+                    done.push(list);
+                }
+                Task::App(len) => {
+                    let mut parts = done.split_off(done.len() - len).into_iter();
+                    let head = parts.next().unwrap();
+                    done.push(parts.fold(head, Term::app));
+                }
+                Task::Lam(vars) => {
+                    self.scope.truncate(self.scope.len() - vars.len());
+                    let body = done.pop().unwrap();
+                    done.push(vars.iter().rev().fold(body, |body, var| Term::Lam(var.clone(), Box::new(body))));
+                }
+            }
+        }
+        Ok(done.pop().unwrap())
+    }
+
+    // Translate an expression into a tree
+    fn tree(&mut self, syntax: &Syntax) -> Result<NodeId, ParseError> {
+        let term = self.term(syntax)?;
+        Ok(term.into_node(self.loader.strategy, &mut self.loader.arena))
+    }
+
+    // Without `as`, the module is referred to by its file name without the
+    // extension
+    fn import(&mut self, span: Span, path: &ModulePath, alias: &Option<String>) -> Result<(), ParseError> {
+        let import = path.path();
+        let alias = match alias {
+            Some(alias) => alias.clone(),
+            None => {
+                let stem = Path::new(&import).file_stem().map(|s| s.to_string_lossy().into_owned());
+                stem.ok_or_else(|| ParseError::new(span, format!("module path `{}`", import), "a path to a file"))?
+            }
+        };
+        if self.modules.contains_key(&alias) {
+            return Err(ParseError::new(span,
+                format!("second module named `{}`", alias), "`as` and a new name"));
+        }
+        let module = self.loader.load(&import, self.path, span)?;
+        self.modules.insert(alias, module);
+        Ok(())
+    }
+
+    fn define(&mut self, span: Span, name: &str, body: &Syntax) -> Result<(), ParseError> {
+        if self.defs.contains_key(name) {
+            return Err(ParseError::new(span,
+                format!("second definition of `{}`", name), "a new name"));
+        }
+        let id = self.tree(body)?;
+        self.defs.insert(name.to_string(), id);
+        Ok(())
+    }
+
+    // Load the imports and expand the definitions of `file`, and return the
+    // tree of its final expression, if it has one
+    fn resolve(&mut self, file: &SourceFile) -> Result<Option<NodeId>, ParseError> {
+        let mut result = None;
+        for item in &file.items {
+            match &item.kind {
+                ItemKind::Import(path, alias) => self.import(item.start, path, alias)?,
+                ItemKind::Definition(name, body) => self.define(item.start, name, body)?,
+                ItemKind::Expr(syntax) => result = Some(self.tree(syntax)?),
+            }
+        }
+        Ok(result)
+    }
+
+    // A program evaluates to its final expression, or to the definition named
+    // `main` if it has none
+    fn resolve_program(&mut self, file: &SourceFile) -> Result<NodeId, ParseError> {
+        if let Some(id) = self.resolve(file)? {
+            return Ok(id);
+        }
+        self.defs.remove("main").ok_or_else(|| ParseError::new(file.end,
+            TokenKind::Eof.to_string(), "an expression or a definition of `main`"))
+    }
+}
+
+// Parse an imported file
+pub fn parse_module(str: &str, path: &Path, loader: &mut Loader) -> Result<Module, ParseError> {
+    let file = parse_syntax(str, false).map_err(|e| e.in_file(Some(path)))?;
+    let mut resolver = Resolver::new(Some(path), loader);
+    resolver.resolve(&file).map_err(|e| e.in_file(Some(path)))?;
+    Ok(Module {defs: resolver.defs})
+}
+
+// Parse a program and everything it imports into a single tree. `path` is the
+// file the program was read from, if any. Definitions are kept in an arena while
+// parsing, so a definition that is referred to many times is only stored once
+// until the final tree is built
+pub fn parse_tree(str: &str, path: Option<&Path>, options: &ParseOptions) -> Result<Expr, ParseError> {
+    crate::debug_log!("\n=== Parsing String into Expr ===\n");
+    let mut loader = Loader::new(options);
+    if let Some(path) = path {
+        loader.enter(path);
+    }
+    let file = parse_source(str).map_err(|e| e.in_file(path))?;
+    let id = Resolver::new(path, &mut loader).resolve_program(&file).map_err(|e| e.in_file(path))?;
+    Ok(loader.arena.to_expr(id))
 }
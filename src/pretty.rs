@@ -0,0 +1,136 @@
+// A pretty printer for trees. It writes the fewest parentheses (only around
+// children that have children of their own), and a tree that does not fit in
+// the line width is broken into its function on one line and every argument
+// indented on its own line:
+//
+//   t
+//     (t t t)
+//     (t
+//       (t t)
+//       t)
+//
+// Subtrees deeper than `max_depth` are written as `…(size N)`, so the shape of
+// a huge tree can be seen without printing all of it
+
+use crate::expr::*;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PrettyOptions {
+    // Lines are only longer than this when a single leaf or `…(size N)` does
+    // not fit
+    pub width: usize,
+    // Number of spaces added for every level of nesting
+    pub indent: usize,
+    pub max_depth: Option<usize>,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        Self {width: 80, indent: 2, max_depth: None}
+    }
+}
+
+struct Printer<'a> {
+    options: &'a PrettyOptions,
+    out: String,
+    // Column of the end of `out`, counted in characters
+    column: usize,
+}
+
+impl Printer<'_> {
+    fn push(&mut self, text: &str) {
+        self.out.push_str(text);
+        self.column += text.chars().count();
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+        self.column = indent;
+    }
+
+    fn is_elided(&self, expr: &Expr, depth: usize) -> bool {
+        !expr.children.is_empty() && self.options.max_depth.is_some_and(|max| depth > max)
+    }
+
+    // Write `expr` on one line into `str`. Gives up and returns false as soon
+    // as `str` is longer than `budget` characters
+    fn flat(&self, expr: &Expr, depth: usize, budget: usize, str: &mut String) -> bool {
+        enum Task<'a> {
+            Node(&'a Expr, usize),
+            Text(&'static str),
+        }
+        let mut len = 0;
+        let mut tasks = vec![Task::Node(expr, depth)];
+        while let Some(task) = tasks.pop() {
+            let text = match task {
+                Task::Node(expr, depth) if self.is_elided(expr, depth) => format!("…(size {})", expr.get_size()),
+                Task::Node(expr, depth) => {
+                    for c in expr.children.iter().rev() {
+                        if c.children.is_empty() || self.is_elided(c, depth + 1) {
+                            tasks.push(Task::Node(c, depth + 1));
+                        } else {
+                            tasks.extend([Task::Text(")"), Task::Node(c, depth + 1), Task::Text("(")]);
+                        }
+                        tasks.push(Task::Text(" "));
+                    }
+                    "t".to_string()
+                }
+                Task::Text(text) => text.to_string(),
+            };
+            len += text.chars().count();
+            if len > budget {
+                return false;
+            }
+            str.push_str(&text);
+        }
+        true
+    }
+
+    fn print(&mut self, expr: &Expr) {
+        enum Task<'a> {
+            // A node starting at the current column, whose arguments are
+            // indented by `indent` if they do not fit on one line
+            Node(&'a Expr, usize, usize),
+            Text(&'static str),
+            Newline(usize),
+        }
+        let mut tasks = vec![Task::Node(expr, 0, 0)];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Node(expr, depth, indent) => {
+                    let mut flat = String::new();
+                    let budget = self.options.width.saturating_sub(self.column);
+                    if self.flat(expr, depth, budget, &mut flat) || self.is_elided(expr, depth) {
+                        if flat.is_empty() {
+                            self.flat(expr, depth, usize::MAX, &mut flat);
+                        }
+                        self.push(&flat);
+                        continue;
+                    }
+                    self.push("t");
+                    // Long chains like lists would be indented past the end of
+                    // the line, so the indentation stops growing at half the
+                    // width
+                    let child_indent = (indent + self.options.indent).min(self.options.width / 2);
+                    for c in expr.children.iter().rev() {
+                        if c.children.is_empty() || self.is_elided(c, depth + 1) {
+                            tasks.push(Task::Node(c, depth + 1, child_indent));
+                        } else {
+                            tasks.extend([Task::Text(")"), Task::Node(c, depth + 1, child_indent), Task::Text("(")]);
+                        }
+                        tasks.push(Task::Newline(child_indent));
+                    }
+                }
+                Task::Text(text) => self.push(text),
+                Task::Newline(indent) => self.newline(indent),
+            }
+        }
+    }
+}
+
+pub fn pretty_expr(expr: &Expr, options: &PrettyOptions) -> String {
+    let mut printer = Printer {options, out: String::new(), column: 0};
+    printer.print(expr);
+    printer.out
+}
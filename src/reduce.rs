@@ -0,0 +1,190 @@
+// A reference evaluator that rewrites `Expr`s with the reduction rules directly,
+// without the interaction net VM. It is slow, but simple enough to be used as
+// an oracle for the rules of the VM and the runtime. The rules are the same as
+// the VM's:
+//   t t b c             = b
+//   t (t a) b c         = a c (b c)
+//   t (t w x) y t       = w
+//   t (t w x) y (t u)   = x u
+//   t (t w x) y (t u v) = y u v
+//
+// A tree is first reduced until it is a leaf, a stem or a fork, and only then
+// are its children reduced, so arguments that are dropped are never evaluated.
+// Like everything else that walks over trees, it keeps its own stack instead of
+// recursing
+
+use crate::expr::*;
+use crate::global::*;
+
+// The rules, named after the combinators they act like
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rule {
+    K,
+    S,
+    // Triage of a leaf, a stem and a fork
+    FLeaf,
+    FStem,
+    FFork,
+}
+
+impl Rule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::K => "K",
+            Rule::S => "S",
+            Rule::FLeaf => "F-leaf",
+            Rule::FStem => "F-stem",
+            Rule::FFork => "F-fork",
+        }
+    }
+}
+
+enum Frame {
+    // The child at `index` of `parent` is reduced to a leaf, a stem or a fork,
+    // so that a rule can be applied to `parent`
+    Rule {parent: Expr, index: usize},
+    // The child at `index` of `parent` is reduced to normal form, after the
+    // ones before it
+    Child {parent: Expr, index: usize},
+}
+
+enum Mode {
+    // Apply rules until the tree is a leaf, a stem or a fork
+    Head,
+    // Reduce the children from this index on
+    Children(usize),
+}
+
+// Apply the rule for the application of `t f b c` to the rest of the arguments.
+// `f` must be a leaf, a stem or a fork, and if it is a fork, so must `c`
+fn apply_rule(mut args: std::vec::IntoIter<Expr>) -> (Rule, Expr) {
+    let mut f = args.next().unwrap();
+    let b = args.next().unwrap();
+    let mut c = args.next().unwrap();
+    let mut f_children = std::mem::take(&mut f.children);
+    let (rule, mut result) = match f_children.len() {
+        0 => (Rule::K, b),
+        1 => {
+            let mut a = f_children.pop().unwrap();
+            let mut bc = b;
+            bc.children.push(c.clone());
+            a.children.extend([c, bc]);
+            (Rule::S, a)
+        }
+        _ => {
+            let x = f_children.pop().unwrap();
+            let w = f_children.pop().unwrap();
+            let mut c_children = std::mem::take(&mut c.children);
+            match c_children.len() {
+                0 => (Rule::FLeaf, w),
+                1 => {
+                    let mut x = x;
+                    x.children.push(c_children.pop().unwrap());
+                    (Rule::FStem, x)
+                }
+                _ => {
+                    let mut y = b;
+                    y.children.append(&mut c_children);
+                    (Rule::FFork, y)
+                }
+            }
+        }
+    };
+    result.children.extend(args);
+    (rule, result)
+}
+
+// A rule applied by `reduce_with`
+pub struct Rewrite<'a> {
+    pub rule: Rule,
+    pub redex: &'a Expr,
+    pub result: &'a Expr,
+    // The trees the redex is in, from the root down
+    frames: &'a [Frame],
+}
+
+impl Rewrite<'_> {
+    // Return the whole tree after the rewrite, and the path to the result in
+    // it: the index of the child to go into at every level from the root
+    pub fn whole_tree(&self) -> (Expr, Vec<usize>) {
+        let mut tree = self.result.clone();
+        for frame in self.frames.iter().rev() {
+            let (Frame::Rule {parent, index} | Frame::Child {parent, index}) = frame;
+            let mut parent = parent.clone();
+            parent.children[*index] = tree;
+            tree = parent;
+        }
+        let path = self.frames.iter()
+            .map(|(Frame::Rule {index, ..} | Frame::Child {index, ..})| *index)
+            .collect();
+        (tree, path)
+    }
+}
+
+// Reduce `expr` to normal form. Does not return if it has none
+pub fn reduce(expr: Expr) -> Expr {
+    reduce_with(expr, None)
+}
+
+// Reduce `expr` to normal form, and call `observer` after every rule
+pub fn reduce_with(expr: Expr, mut observer: Option<&mut dyn FnMut(&Rewrite)>) -> Expr {
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut focus = expr;
+    let mut mode = Mode::Head;
+    loop {
+        match mode {
+            Mode::Head if focus.children.len() >= 3 => {
+                // The first argument must be a value to know which rule
+                // applies, and for triage, so must the third one
+                let index = if focus.children[0].children.len() >= 3 {
+                    Some(0)
+                } else if focus.children[0].children.len() == 2 && focus.children[2].children.len() >= 3 {
+                    Some(2)
+                } else {
+                    None
+                };
+                match index {
+                    Some(index) => {
+                        let child = std::mem::replace(&mut focus.children[index], Expr::leaf());
+                        stack.push(Frame::Rule {parent: focus, index});
+                        focus = child;
+                    }
+                    None => {
+                        crate::debug_log!("Reduce: {}\n", focus);
+                        let redex = observer.is_some().then(|| focus.clone());
+                        let (rule, result) = apply_rule(std::mem::take(&mut focus.children).into_iter());
+                        focus = result;
+                        if let (Some(observer), Some(redex)) = (observer.as_mut(), redex) {
+                            observer(&Rewrite {rule, redex: &redex, result: &focus, frames: &stack});
+                        }
+                    }
+                }
+            }
+            Mode::Head => match stack.pop() {
+                Some(Frame::Rule {mut parent, index}) => {
+                    parent.children[index] = focus;
+                    focus = parent;
+                }
+                frame => {
+                    stack.extend(frame);
+                    mode = Mode::Children(0);
+                }
+            },
+            Mode::Children(index) if index < focus.children.len() => {
+                let child = std::mem::replace(&mut focus.children[index], Expr::leaf());
+                stack.push(Frame::Child {parent: focus, index});
+                focus = child;
+                mode = Mode::Head;
+            }
+            Mode::Children(_) => match stack.pop() {
+                Some(Frame::Child {mut parent, index}) => {
+                    parent.children[index] = focus;
+                    focus = parent;
+                    mode = Mode::Children(index + 1);
+                }
+                Some(Frame::Rule {..}) => unreachable!("children are only reduced after the rules"),
+                None => return focus,
+            },
+        }
+    }
+}
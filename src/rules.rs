@@ -30,6 +30,14 @@ pub const RULES_NAME: [&str; 15] = [
     "RULE_F_E", "RULE_F_D", "RULE_F_A", "RULE_F_T", "RULE_F_Q",
 ];
 
+// Return the index in `RULES` of the rule for an active pair of agents of these
+// types, if there is one
+pub fn rule_index(left: AgentType, right: AgentType) -> Option<usize> {
+    let left = [AgentType::L, AgentType::S, AgentType::F].iter().position(|t| *t == left)?;
+    let right = [AgentType::E, AgentType::D, AgentType::A, AgentType::T, AgentType::Q].iter().position(|t| *t == right)?;
+    Some(left * 5 + right)
+}
+
 // L >< E => ;
 pub const RULE_L_E: [Instr; 1] = [
     Instr::Return
@@ -0,0 +1,97 @@
+// A program that is evaluated once, and then applied to many arguments. The
+// normal form of the program stays in the VM, and every call connects it to an
+// argument with an A agent, through a D agent that keeps a copy for the next
+// call. The work the program does before it needs its argument is only done
+// once, and the net of the result is freed after it is read back, so the VM
+// does not grow from call to call. A call that stops early leaves rules that
+// are not applied in the net, so it is rebuilt from the normal form instead
+
+use std::fmt;
+
+use crate::expr::*;
+use crate::global::*;
+use crate::vm::*;
+
+pub struct Session {
+    vm: VM,
+    // The interface the program is connected to
+    program: HeapAddress,
+    // The normal form of the program, to rebuild the net from
+    normal_form: Expr,
+    max_interactions: u64,
+    max_live_agents: usize,
+    calls: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CallError {
+    // The evaluation stopped before the normal form
+    Stopped(EvalStatus),
+    // The net could not be applied to the argument, or read back
+    Readback(ReadbackError),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Stopped(status) => write!(f, "evaluation stopped: {:?}", status),
+            CallError::Readback(err) => write!(f, "readback failed: {}", err),
+        }
+    }
+}
+
+impl From<ReadbackError> for CallError {
+    fn from(err: ReadbackError) -> Self {
+        CallError::Readback(err)
+    }
+}
+
+impl Session {
+    // Evaluate `program`. Does not return if it has no normal form
+    pub fn new(program: Expr) -> Result<Self, ReadbackError> {
+        let mut vm = VM::from_expr(program);
+        vm.eval();
+        let program = *vm.interfaces().first().ok_or(ReadbackError::NoInterface)?;
+        let normal_form = vm.try_readback()?;
+        Ok(Self {vm, program, normal_form, max_interactions: u64::MAX, max_live_agents: usize::MAX, calls: 0})
+    }
+
+    // Limit every call like `VM::eval_with_limits`
+    pub fn set_limits(&mut self, max_interactions: u64, max_live_agents: usize) {
+        self.max_interactions = max_interactions;
+        self.max_live_agents = max_live_agents;
+    }
+
+    // The VM, to set a cancellation token or look at its counters
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut VM {
+        &mut self.vm
+    }
+
+    pub fn call_count(&self) -> u64 {
+        self.calls
+    }
+
+    // Apply the program to `arg` and return the normal form of the result. If
+    // the evaluation stops early, the result is dropped and the net is rebuilt
+    // from the normal form of the program, so the next call starts afresh
+    pub fn call(&mut self, arg: Expr) -> Result<Expr, CallError> {
+        self.calls += 1;
+        let result = self.vm.apply_interface(self.program, arg)?;
+        let status = self.vm.eval_with_limits(self.max_interactions, self.max_live_agents);
+        if status != EvalStatus::Finished {
+            self.vm.reset(self.normal_form.clone());
+            self.vm.eval();
+            self.program = self.vm.interfaces()[0];
+            return Err(CallError::Stopped(status));
+        }
+        // The result is erased even if it can not be read back
+        let expr = self.vm.readback_interface(result);
+        self.vm.erase_interface(result)?;
+        self.vm.eval();
+        Ok(expr?)
+    }
+}
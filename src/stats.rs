@@ -0,0 +1,59 @@
+// Counters the VM keeps while it evaluates, to catch performance regressions.
+// They are printed as a table or as JSON by `--stats`
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::rules::*;
+
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    // Number of interactions of every rule, in the order of `RULES`
+    pub rule_counts: [u64; RULES_NAME.len()],
+    pub agents_allocated: u64,
+    pub agents_freed: u64,
+    pub peak_live_agents: usize,
+    pub peak_active_pairs: usize,
+    // Time spent in `VM::eval` and `VM::eval_with_limits`
+    pub wall_time: Duration,
+}
+
+// The name of a rule without its prefix, like "F-D" for "RULE_F_D"
+pub fn short_rule_name(index: usize) -> String {
+    RULES_NAME[index].trim_start_matches("RULE_").replace('_', "-")
+}
+
+impl Stats {
+    pub fn interactions(&self) -> u64 {
+        self.rule_counts.iter().sum()
+    }
+
+    // Write the counters as a JSON object on one line
+    pub fn to_json(&self) -> String {
+        let rules: Vec<String> = self.rule_counts.iter().enumerate()
+            .map(|(i, count)| format!("\"{}\": {}", short_rule_name(i), count))
+            .collect();
+        format!("{{\"interactions\": {}, \"rules\": {{{}}}, \"agents_allocated\": {}, \"agents_freed\": {}, \
+            \"peak_live_agents\": {}, \"peak_active_pairs\": {}, \"wall_time_ms\": {:.3}}}",
+            self.interactions(), rules.join(", "), self.agents_allocated, self.agents_freed,
+            self.peak_live_agents, self.peak_active_pairs, self.wall_time.as_secs_f64() * 1000.0)
+    }
+}
+
+// A table with one counter per line. Rules that never ran are left out
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let interactions = self.interactions();
+        writeln!(f, "{:<20}{:>12}", "Rule", "Count")?;
+        for (i, count) in self.rule_counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+            let share = *count as f64 / interactions as f64 * 100.0;
+            writeln!(f, "{:<20}{:>12} {:>5.1}%", short_rule_name(i), count, share)?;
+        }
+        writeln!(f, "{:<20}{:>12}", "Total", interactions)?;
+        writeln!(f, "{:<20}{:>12}", "Agents allocated", self.agents_allocated)?;
+        writeln!(f, "{:<20}{:>12}", "Agents freed", self.agents_freed)?;
+        writeln!(f, "{:<20}{:>12}", "Peak live agents", self.peak_live_agents)?;
+        writeln!(f, "{:<20}{:>12}", "Peak active pairs", self.peak_active_pairs)?;
+        write!(f, "{:<20}{:>9.3} ms", "Wall time", self.wall_time.as_secs_f64() * 1000.0)
+    }
+}
@@ -1,8 +1,30 @@
 use crate::agent::*;
+use crate::arena::*;
+use crate::binary::*;
 use crate::code::*;
+use crate::codec::*;
+use crate::debugger::*;
+use crate::explain::*;
+use crate::expr::*;
+use crate::format::*;
 use crate::global::*;
+use crate::lambda::*;
+use crate::machine::*;
+use crate::memo::*;
+use crate::notation::*;
+use crate::parse::*;
+use crate::pretty::*;
+use crate::reduce::*;
+use crate::rules::*;
+use crate::session::*;
+use crate::stats::*;
+use crate::trace::*;
+
+use std::fs;
+use std::path::PathBuf;
 use crate::vm::*;
 
+#[test]
 fn test_rules() {
     test_rule("L-E", Code::from_instrs(&[
         Instr::MkAgent(0, AgentType::L),
@@ -210,8 +232,833 @@ fn test_rule(rule_name: &str, code: Code) {
     crate::debug_log!("\n >>> Testing rule {} <<< \n", rule_name);
     let mut vm = VM::from_code(code);
     vm.eval();
-    if !vm.is_empty() {
-        crate::debug_log!("INCORRECT");
-        std::process::exit(1);
+    assert!(vm.is_empty(), "rule {} is incorrect", rule_name);
+}
+fn parse(src: &str) -> Result<Expr, ParseError> {
+    parse_tree(src, None, &ParseOptions::default())
+}
+
+fn parse_with(src: &str, strategy: Strategy) -> Result<Expr, ParseError> {
+    parse_tree(src, None, &ParseOptions {strategy, ..ParseOptions::default()})
+}
+
+#[test]
+fn test_parse_tree() {
+    let expr = parse("t (t t) # a comment\n  (t t t) t").unwrap();
+    assert_eq!(expr.to_string(), "t(tt)(ttt)t");
+    assert_eq!(parse("((t t) t)").unwrap().to_string(), "ttt");
+}
+
+#[test]
+fn test_parse_errors() {
+    let err = parse("t (t t))").unwrap_err();
+    assert_eq!((err.span.line, err.span.column), (1, 8));
+    assert_eq!(err.found, "`)`");
+    assert_eq!(err.expected, "end of input");
+
+    let err = parse("t\n(t $)").unwrap_err();
+    assert_eq!((err.span.line, err.span.column), (2, 4));
+    assert_eq!(err.found, "`$`");
+
+    let err = parse("t (t").unwrap_err();
+    assert_eq!(err.found, "end of input");
+    assert_eq!(err.expected, "`)`");
+
+    assert!(parse("# nothing here\n").is_err());
+}
+
+#[test]
+fn test_parse_definitions() {
+    let src = "
+        # K combinator
+        k = t t
+        id = t (t k) k
+        main = id (k t)
+    ";
+    assert_eq!(parse(src).unwrap().to_string(), "t(t(tt))(tt)(ttt)");
+    assert_eq!(parse("k = t t\nmain = k k").unwrap().to_string(), "tt(tt)");
+
+    let err = parse("k = t t\nmain = k x").unwrap_err();
+    assert_eq!((err.span.line, err.span.column), (2, 10));
+    assert_eq!(err.found, "undefined name `x`");
+    assert!(parse("k = t\nk = t t\nmain = k").is_err());
+    assert!(parse("k = t t").is_err());
+
+    // A final expression is the result, the body of `k` ends with its line
+    assert_eq!(parse("k = t t\nk k").unwrap().to_string(), "tt(tt)");
+    assert_eq!(parse("  k = t t\n    t\n  k k").unwrap().to_string(), "ttt(ttt)");
+    assert_eq!(parse("k = t (t\nt)\nk").unwrap().to_string(), "t(tt)");
+    let err = parse("main = t t\nt").unwrap_err();
+    assert_eq!((err.span.line, err.span.column), (2, 1));
+    assert!(parse("k = t t\nk k\nj = t").is_err());
+}
+
+fn eval_to_string(src: &str, strategy: Strategy) -> String {
+    let mut vm = VM::from_expr(parse_with(src, strategy).unwrap());
+    vm.eval();
+    vm.readback().to_string()
+}
+
+#[test]
+fn test_lambda() {
+    let src = "
+        true = \\x y. x
+        false = \\x y. y
+        not = \\b. b false true
+        main = not false (t t) t
+    ";
+    for strategy in [Strategy::Naive, Strategy::Constant, Strategy::Eta] {
+        assert_eq!(eval_to_string(src, strategy), "tt");
+        assert_eq!(eval_to_string("(\\x. x) (t t)", strategy), "tt");
+        assert_eq!(eval_to_string("(\\f x. f (f x)) (\\y. t y) t", strategy), "t(tt)");
     }
-}
\ No newline at end of file
+
+    let swap = "\\f x y. f y x";
+    let naive = parse_with(swap, Strategy::Naive).unwrap().get_size();
+    let eta = parse_with(swap, Strategy::Eta).unwrap().get_size();
+    assert!(eta < naive);
+
+    let err = parse("\\x. y").unwrap_err();
+    assert_eq!(err.found, "undefined name `y`");
+
+    // Long lists, with or without a lambda around them, do not overflow the
+    // stack
+    let n = 100_000;
+    let list = format!("[{}]", vec!["t"; n].join(", "));
+    assert_eq!(parse(&list).unwrap().to_string(), deep_list(n).to_string());
+    let list = format!("\\x. [{}]", vec!["x"; n].join(", "));
+    assert!(parse_with(&list, Strategy::Naive).unwrap().get_size() > n as u64);
+}
+
+#[test]
+fn test_literals() {
+    assert_eq!(parse("0").unwrap().to_string(), "t");
+    assert_eq!(parse("1").unwrap().to_string(), "t(tt)t");
+    assert_eq!(parse("6").unwrap().to_string(), "tt(t(tt)(t(tt)t))");
+    // 2^70 is 70 false bits and a true bit
+    assert_eq!(parse("1180591620717411303424").unwrap().get_size(), 70 * 2 + 3 + 1);
+    assert_eq!(parse("[]").unwrap().to_string(), "t");
+    assert_eq!(parse("[false, true,]").unwrap().to_string(), "tt(t(tt)t)");
+    assert_eq!(parse("\"\\n\"").unwrap().to_string(), parse("[10]").unwrap().to_string());
+    assert_eq!(parse("\"é\"").unwrap().to_string(), parse("[195, 169]").unwrap().to_string());
+    assert_eq!(eval_to_string("(\\x. [x, x]) t", Strategy::default()), "tt(ttt)");
+
+    let err = parse("[t t").unwrap_err();
+    assert_eq!(err.expected, "`,` or `]`");
+    let err = parse("\"abc").unwrap_err();
+    assert_eq!(err.found, "unterminated string");
+}
+
+// Write `files` into a fresh directory under the system temp directory
+fn write_files(dir_name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(dir_name);
+    let _ = fs::remove_dir_all(&dir);
+    for (name, src) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, src).unwrap();
+    }
+    dir
+}
+
+fn parse_file(path: &PathBuf, options: &ParseOptions) -> Result<Expr, ParseError> {
+    parse_tree(&fs::read_to_string(path).unwrap(), Some(path), options)
+}
+
+#[test]
+fn test_imports() {
+    let dir = write_files("tc-test-imports", &[
+        ("main.tc", "import \"lists.tc\"\nimport stdlib/bool as b\nmain = lists.pair b.true b.false"),
+        ("lists.tc", "import stdlib/bool\npair = \\x y. [x, y]\nnil = bool.false"),
+        ("stdlib/bool.tc", "true = t t\nfalse = t"),
+    ]);
+    let expr = parse_file(&dir.join("main.tc"), &ParseOptions::default()).unwrap();
+    let mut vm = VM::from_expr(expr);
+    vm.eval();
+    assert_eq!(vm.readback().to_string(), parse("[true, false]").unwrap().to_string());
+
+    // Bare paths are also looked up in the include directories
+    let other = write_files("tc-test-imports-other", &[("main.tc", "import stdlib/bool\nmain = bool.true")]);
+    let options = ParseOptions {include_dirs: vec![dir.clone()], ..ParseOptions::default()};
+    assert_eq!(parse_file(&other.join("main.tc"), &options).unwrap().to_string(), "tt");
+    assert!(parse_file(&other.join("main.tc"), &ParseOptions::default()).is_err());
+}
+
+#[test]
+fn test_import_errors() {
+    let dir = write_files("tc-test-import-errors", &[
+        ("a.tc", "import b\nmain = b.x"),
+        ("b.tc", "import c\nx = c.y"),
+        ("c.tc", "import a\ny = t"),
+        ("d.tc", "import e\nmain = e.missing"),
+        ("e.tc", "x = t\ny = (t"),
+    ]);
+    let options = ParseOptions::default();
+    let err = parse_file(&dir.join("a.tc"), &options).unwrap_err();
+    assert!(err.found.starts_with("import cycle"), "{}", err.found);
+    assert!(err.file.unwrap().ends_with("c.tc"));
+
+    let err = parse_file(&dir.join("d.tc"), &options).unwrap_err();
+    assert!(err.file.unwrap().ends_with("e.tc"));
+    assert_eq!((err.span.line, err.span.column), (2, 7));
+
+    let err = parse("import nowhere/nothing\nmain = t").unwrap_err();
+    assert_eq!(err.found, "missing module `nowhere/nothing`");
+}
+
+#[test]
+fn test_notations() {
+    let expr = parse("△ (Δ △) (t t t) △").unwrap();
+    assert_eq!(expr.to_string(), "t(tt)(ttt)t");
+    assert_eq!(format_expr(&expr, Notation::Delta).unwrap(), "△ (△ △) (△ △ △) △");
+    assert!(format_expr(&expr, Notation::Ternary).is_err());
+
+    let value = parse("t (t t) (t t t)").unwrap();
+    assert_eq!(format_expr(&value, Notation::Ternary).unwrap(), "210200");
+    assert_eq!(parse_ternary("21 02\n00").unwrap().to_string(), value.to_string());
+
+    let err = parse_ternary("210").unwrap_err();
+    assert_eq!(err.found, "end of input");
+    let err = parse_ternary("100").unwrap_err();
+    assert_eq!((err.span.column, err.found.as_str()), (3, "`0`"));
+    assert!(parse_ternary("13").is_err());
+}
+
+// A list of `n` leaves, which is a chain of `n` forks
+fn deep_list(n: usize) -> Expr {
+    (0..n).fold(Expr::leaf(), |rest, _| Expr::fork(Expr::leaf(), rest))
+}
+
+#[test]
+fn test_deep_expr() {
+    let n = 100_000;
+    let expr = deep_list(n);
+    assert_eq!(expr.get_size(), 2 * n as u64 + 1);
+    let copy = expr.clone();
+    assert_eq!(copy.to_string().len(), expr.to_string().len());
+    assert_eq!(format_expr(&expr, Notation::Ternary).unwrap().len(), 2 * n + 1);
+    assert!(format_expr(&expr, Notation::Delta).is_ok());
+    drop(copy);
+    // The printed tree parses back, however deeply it is nested
+    let src = expr.to_string();
+    assert_eq!(parse(&src).unwrap().to_string(), src);
+
+    let n = 5_000;
+    let mut vm = VM::from_expr(deep_list(n));
+    vm.eval();
+    assert_eq!(vm.readback().get_size(), 2 * n as u64 + 1);
+}
+
+#[test]
+fn test_arena() {
+    let mut arena = Arena::new();
+    let expr = parse("t (t t t) (t (t t t)) (t t t)").unwrap();
+    let id = arena.intern_expr(&expr);
+    assert_eq!(arena.to_expr(id).to_string(), expr.to_string());
+    // t, t t t, t (t t t), the fork of the last two, and the application
+    assert_eq!(arena.node_count(), 5);
+    assert_eq!(arena.intern_expr(&expr.clone()), id);
+
+    let leaf = arena.leaf();
+    let k = arena.app(leaf, leaf);
+    assert_eq!(arena.node(k), Node::Stem(leaf));
+    let kk = arena.app(k, k);
+    assert_eq!(arena.node(kk), Node::Fork(leaf, k));
+    let app = arena.app(kk, leaf);
+    assert_eq!(arena.node(app), Node::App(kk, leaf));
+
+    // Repeated subtrees are only stored once
+    let list = parse("l = [1, 2, 3]\nmain = [l, l, l, l]").unwrap();
+    let mut arena = Arena::new();
+    arena.intern_expr(&list);
+    assert!((arena.node_count() as u64) < list.get_size() / 4);
+}
+
+#[test]
+fn test_codec() {
+    assert_eq!(decode_bool(&encode_bool(true)), Some(true));
+    assert_eq!(decode_nat(&encode_nat(0)), Some(0));
+    assert_eq!(decode_nat(&encode_nat(u64::MAX)), Some(u64::MAX));
+    assert_eq!(encode_nat(6).to_string(), parse("6").unwrap().to_string());
+    let big = "1180591620717411303424";
+    assert_eq!(decode_nat(&encode_nat_decimal(big).unwrap()), None);
+    assert_eq!(decode_nat_decimal(&encode_nat_decimal(big).unwrap()).as_deref(), Some(big));
+    assert!(encode_nat_decimal("12a").is_none());
+    assert_eq!(decode_int(&encode_int(-42)), Some(-42));
+    assert_eq!(decode_int(&encode_int(i64::MIN)), Some(i64::MIN));
+    assert_eq!(decode_string(&encode_string("héllo")).as_deref(), Some("héllo"));
+    assert_eq!(decode_string(&encode_bytes(&[0xff])), None);
+    assert_eq!(decode_nat(&encode_list(vec![encode_nat(2)])), None);
+
+    let ty = Type::from_name("list(pair(int, option(string)))").unwrap();
+    let expr = encode_list(vec![
+        encode_pair(encode_int(-1), encode_option(Some(encode_string("a")))),
+        encode_pair(encode_int(2), encode_option(None)),
+    ]);
+    let value = Value::decode(&expr, &ty).unwrap();
+    assert_eq!(value.to_string(), "[(-1, some(\"a\")), (2, none)]");
+    assert!(Value::decode(&expr, &Type::Nat).is_none());
+    assert_eq!(Type::from_name("pair(nat)"), None);
+    assert_eq!(Type::from_name("list(nat"), None);
+
+    let mut vm = VM::from_expr(parse("(\\x y. y) 42 \"abc\"").unwrap());
+    vm.eval();
+    assert_eq!(vm.readback_value(&Type::String).unwrap().to_string(), "\"abc\"");
+}
+
+#[test]
+fn test_binary() {
+    let expr = parse("l = [1, 2, 3]\nmain = [l, l, l, l] t (t t)").unwrap();
+    let mut bytes = Vec::new();
+    write_expr(&expr, &mut bytes).unwrap();
+    assert_eq!(&bytes[..MAGIC.len()], MAGIC);
+    assert_eq!(read_expr(&mut bytes.as_slice()).unwrap().to_string(), expr.to_string());
+    // Shared subtrees are only written once
+    assert!((bytes.len() as u64) < expr.get_size());
+
+    let list = deep_list(100_000);
+    let mut bytes = Vec::new();
+    write_expr(&list, &mut bytes).unwrap();
+    assert_eq!(read_expr(&mut bytes.as_slice()).unwrap().get_size(), list.get_size());
+
+    assert!(matches!(read_expr(&mut &b"t t"[..]), Err(ReadError::BadMagic)));
+    assert!(matches!(read_expr(&mut &b"TCDAG\x09\x01\x00"[..]), Err(ReadError::UnsupportedVersion(9))));
+    // A stem that refers to itself, and a file that ends too early
+    assert!(matches!(read_expr(&mut &b"TCDAG\x01\x01\x01\x00"[..]), Err(ReadError::Corrupt(_))));
+    assert!(matches!(read_expr(&mut &b"TCDAG\x01\x02\x00"[..]), Err(ReadError::Corrupt(_))));
+}
+
+#[test]
+fn test_pretty() {
+    let expr = parse("t (t t t) (t (t t) t)").unwrap();
+    let options = PrettyOptions::default();
+    assert_eq!(pretty_expr(&expr, &options), "t (t t t) (t (t t) t)");
+    let narrow = PrettyOptions {width: 12, ..options};
+    assert_eq!(pretty_expr(&expr, &narrow), "t\n  (t t t)\n  (t (t t) t)");
+    let narrower = PrettyOptions {width: 8, ..options};
+    assert_eq!(pretty_expr(&expr, &narrower), "t\n  (t t t)\n  (t\n    (t t)\n    t)");
+    let shallow = PrettyOptions {max_depth: Some(1), ..options};
+    assert_eq!(pretty_expr(&expr, &shallow), "t (t t t) (t …(size 2) t)");
+    // Deep trees do not overflow the stack
+    assert!(pretty_expr(&deep_list(100_000), &narrow).lines().count() > 100_000);
+}
+
+#[test]
+fn test_format_source() {
+    let options = PrettyOptions::default();
+    let src = "\
+# Comment
+import a/b   as c
+not = \\x .   \\ y  . ((t (t t)) (x)) y  # negate
+
+
+list = [ 1,2 , 003, (\\x.x) , \"a\\n\" ]
+main = (\\a b. a) c.d (t # inner
+  t)
+";
+    let expected = "\
+# Comment
+import a/b as c
+not = \\x y. t (t t) x y # negate
+
+list = [1, 2, 3, \\x. x, \"a\\n\"]
+# inner
+main = (\\a b. a) c.d (t t)
+";
+    let formatted = format_source(src, &options).unwrap();
+    assert_eq!(formatted, expected);
+    assert_eq!(format_source(&formatted, &options).unwrap(), formatted);
+
+    let src = "f = \\x y. x\nmain = f (t t t t t t t t) [t t, t t t, t t t t, t t t t t, t t t t t t]";
+    let narrow = PrettyOptions {width: 30, ..options};
+    let formatted = format_source(src, &narrow).unwrap();
+    assert!(formatted.lines().all(|line| line.chars().count() <= 30));
+    assert_eq!(parse(&formatted).unwrap().to_string(), parse(src).unwrap().to_string());
+    assert_eq!(format_source(&formatted, &narrow).unwrap(), formatted);
+
+    assert!(format_source("x = (t", &options).is_err());
+    // Deeply nested sources do not overflow the stack
+    let deep = deep_list(100_000).to_string();
+    let formatted = format_source(&deep, &options).unwrap();
+    assert!(formatted.lines().count() > 100_000);
+    assert_eq!(parse(&formatted).unwrap().to_string(), deep);
+
+    // The body of a definition ends like it does for the parser
+    assert_eq!(format_source("k = (t t)\n  t # k\n(k) k", &options).unwrap(), "k = t t t # k\nk k\n");
+}
+
+#[test]
+fn test_reference_reducer() {
+    let programs = [
+        "t t (t t) t",
+        "t (t (t t)) t t",
+        "t (t t t) t t",
+        "t (t t t) t (t t)",
+        "t (t t t) t (t t t)",
+        "(\\x y. y x) 6 (\\n. [n, n])",
+        "not = \\b. b false (\\x. true) (\\x y. false)\nmain = [not true, not false]",
+        "(\\f x. f (f x)) (\\n. t n) 1",
+    ];
+    for src in programs {
+        let mut vm = VM::from_expr(parse(src).unwrap());
+        vm.eval();
+        assert_eq!(reduce(parse(src).unwrap()).to_string(), vm.readback().to_string(), "{}", src);
+    }
+
+    // Arguments that are dropped are not evaluated, even if they have no
+    // normal form
+    let omega = "w = \\x. x x\nmain = (\\x y. x) t (w w)";
+    assert_eq!(reduce(parse(omega).unwrap()).to_string(), "t");
+
+    let list = deep_list(100_000);
+    assert_eq!(reduce(list.clone()).get_size(), list.get_size());
+}
+
+#[test]
+fn test_memo_evaluator() {
+    let programs = [
+        "t (t (t t)) t t",
+        "t (t t t) t (t t t)",
+        "(\\x y. y x) 6 (\\n. [n, n])",
+        "not = \\b. b false (\\x. true) (\\x y. false)\nmain = (\\f. [f true, f false, f true]) not",
+    ];
+    for src in programs {
+        let mut evaluator = MemoEvaluator::new();
+        let result = evaluator.eval_expr(&parse(src).unwrap());
+        assert_eq!(result.to_string(), reduce(parse(src).unwrap()).to_string(), "{}", src);
+    }
+
+    // The second `f true` is found in the cache
+    let mut evaluator = MemoEvaluator::new();
+    evaluator.eval_expr(&parse(programs[3]).unwrap());
+    let stats = evaluator.stats;
+    evaluator.eval_expr(&parse(programs[3]).unwrap());
+    assert_eq!(evaluator.stats.misses, stats.misses);
+    assert!(stats.hits > 0 && stats.hit_rate() > 0.0 && stats.hit_rate() < 1.0);
+    assert!(evaluator.cache_size() as u64 <= stats.misses);
+    let mut vm = VM::from_expr(parse(programs[3]).unwrap());
+    vm.eval();
+    assert!(vm.interaction_count() > stats.misses);
+
+    let list = deep_list(100_000);
+    assert_eq!(MemoEvaluator::new().eval_expr(&list).get_size(), list.get_size());
+}
+
+#[test]
+fn test_machine() {
+    assert_eq!(compile_ops(&parse("t (t t) t").unwrap()),
+        [Op::Leaf, Op::Leaf, Op::Leaf, Op::Apply, Op::Apply, Op::Leaf, Op::Apply]);
+    let programs = [
+        "t (t (t t)) t t",
+        "t (t t t) t (t t t)",
+        "(\\x y. y x) 6 (\\n. [n, n])",
+        "not = \\b. b false (\\x. true) (\\x y. false)\nmain = (\\f. [f true, f false]) not",
+    ];
+    for src in programs {
+        let mut machine = Machine::from_expr(&parse(src).unwrap());
+        assert!(machine.readback().is_none());
+        machine.eval();
+        assert_eq!(machine.readback().unwrap().to_string(), reduce(parse(src).unwrap()).to_string(), "{}", src);
+        assert!(machine.step_count() > 0);
+    }
+
+    let mut machine = Machine::from_expr(&deep_list(100_000));
+    machine.eval();
+    assert_eq!(machine.readback().unwrap().get_size(), deep_list(100_000).get_size());
+}
+
+#[test]
+fn test_explain() {
+    let mut out = Vec::new();
+    let result = explain(parse("t (t (t t) t t) (t (t t) t t)").unwrap(), &ExplainOptions::default(), &mut out).unwrap();
+    assert_eq!(result.to_string(), "t(tt(tt))(tt(tt))");
+    assert_eq!(String::from_utf8(out).unwrap(), "\
+1. S: t (t t) t t => t t (t t)
+   t («t t (t t)») (t (t t) t t)
+2. S: t (t t) t t => t t (t t)
+   t (t t (t t)) («t t (t t)»)
+");
+
+    let mut rules = Vec::new();
+    let src = "t (t t t) t t\nt (t t t) t (t t)\nt (t t t) t (t t t)";
+    for line in src.lines() {
+        reduce_with(parse(line).unwrap(), Some(&mut |rewrite: &Rewrite| rules.push(rewrite.rule)));
+    }
+    assert_eq!(rules, [Rule::FLeaf, Rule::FStem, Rule::FFork]);
+
+    let mut out = Vec::new();
+    let options = ExplainOptions {max_depth: Some(0)};
+    explain(parse("t (t t) (t t t) t").unwrap(), &options, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "\
+1. S: t …(size 2) …(size 3) t => t t …(size 4)
+   «t t …(size 4)»
+2. K: t t t t => t
+   t t «t»
+");
+}
+
+#[test]
+fn test_eval_with_limits() {
+    let src = "not = \\b. b false (\\x. true) (\\x y. false)\nmain = (\\f. [f true, f false]) not";
+    let mut vm = VM::from_expr(parse(src).unwrap());
+    vm.eval();
+    let expected = vm.readback().to_string();
+    let total = vm.interaction_count();
+
+    // Resume in small slices
+    let mut vm = VM::from_expr(parse(src).unwrap());
+    let mut calls = 0;
+    while vm.eval_with_limits(7, usize::MAX) == EvalStatus::OutOfFuel {
+        calls += 1;
+        assert_eq!(vm.interaction_count(), calls * 7);
+    }
+    assert_eq!(vm.interaction_count(), total);
+    assert_eq!(vm.readback().to_string(), expected);
+    assert_eq!(vm.eval_with_limits(0, 0), EvalStatus::Finished);
+
+    let omega = "w = \\x. x x\nmain = w w";
+    let mut vm = VM::from_expr(parse(omega).unwrap());
+    assert_eq!(vm.eval_with_limits(10_000, usize::MAX), EvalStatus::OutOfFuel);
+    assert_eq!(vm.eval_with_limits(10_000, usize::MAX), EvalStatus::OutOfFuel);
+    assert_eq!(vm.interaction_count(), 20_000);
+    assert_eq!(vm.eval_with_limits(u64::MAX, 10), EvalStatus::OutOfMemory);
+}
+
+#[test]
+fn test_cancel_and_progress() {
+    use std::sync::{Arc, Mutex};
+
+    let omega = "w = \\x. x x\nmain = w w";
+    let mut vm = VM::from_expr(parse(omega).unwrap());
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let token = CancelToken::new();
+    vm.set_cancel_token(token.clone());
+    {
+        let reports = reports.clone();
+        vm.set_progress_callback(500, move |progress| {
+            reports.lock().unwrap().push(progress.interactions);
+            if progress.interactions == 3000 {
+                token.cancel();
+            }
+        });
+    }
+    assert_eq!(vm.eval(), EvalStatus::Cancelled);
+    // The token is only checked every `CANCEL_CHECK_INTERVAL` interactions
+    assert_eq!(vm.interaction_count(), 3072);
+    assert_eq!(*reports.lock().unwrap(), [500, 1000, 1500, 2000, 2500, 3000]);
+    assert!(vm.progress().live_agents > 0 && vm.progress().active_pairs > 0);
+    assert_eq!(vm.step(), EvalState::EvalCancelled);
+
+    // Cancel from another thread
+    let token = CancelToken::new();
+    let mut vm = VM::from_expr(parse(omega).unwrap());
+    vm.set_cancel_token(token.clone());
+    let handle = std::thread::spawn(move || vm.eval());
+    token.cancel();
+    assert_eq!(handle.join().unwrap(), EvalStatus::Cancelled);
+}
+
+#[test]
+fn test_intermediate_readback() {
+    let srcs = [
+        "t (t (t t) t t) (t (t t) t t)",
+        "not = \\b. b false (\\x. true) (\\x y. false)\nmain = (\\f. [f true, f false]) not",
+        "main = (\\x. [x, x]) (t t t t)",
+    ];
+    for src in srcs {
+        let expected = reduce(parse(src).unwrap()).to_string();
+        let mut vm = VM::from_expr(parse(src).unwrap());
+        // Every state reads back as a tree with the same normal form
+        loop {
+            assert_eq!(reduce(vm.readback()).to_string(), expected, "{}", src);
+            if vm.step() == EvalState::EvalFinished {
+                break;
+            }
+        }
+        assert_eq!(vm.readback().to_string(), expected, "{}", src);
+    }
+
+    // A pending application is read back as one
+    let vm = VM::from_expr(parse("t (t t t) t t").unwrap());
+    assert_eq!(vm.readback().to_string(), "t(ttt)tt");
+}
+
+#[test]
+fn test_interface_pairs() {
+    // t (t t), with the stem connected to the interface by its main port, and
+    // that pair on top of the stack, above the application of the leaf
+    let code = Code::from_instrs(&[
+        Instr::MkAgent(0, AgentType::L),
+        Instr::MkAgent(1, AgentType::I),
+        Instr::MkAgent(2, AgentType::S),
+        Instr::MkAgent(3, AgentType::A),
+        Instr::MkAgent(4, AgentType::L),
+        Instr::Connect(0, PortNum::Main, 3, PortNum::Main, ConnectMode::NoRef),
+        Instr::Connect(4, PortNum::Main, 3, PortNum::P0, ConnectMode::NoRef),
+        Instr::Connect(3, PortNum::P1, 2, PortNum::P0, ConnectMode::NoRef),
+        Instr::Connect(2, PortNum::Main, 1, PortNum::Main, ConnectMode::NoRef),
+        Instr::Return,
+    ]);
+    let mut vm = VM::from_code(code);
+    assert_eq!(vm.active_pair_count(), 2);
+    assert_eq!(vm.eval(), EvalStatus::Finished);
+    assert_eq!(vm.interaction_count(), 1);
+    assert_eq!(vm.readback().to_string(), "t(tt)");
+}
+
+#[test]
+fn test_open_nets() {
+    // Triage: false (a leaf) gives true, and true (a stem) gives false
+    let not = "t (t (t t) (t t t)) t";
+    let parse_main = |src: &str| parse(&format!("main = {}", src)).unwrap();
+    let true_ = parse_main("true");
+    let false_ = parse_main("false");
+    let not_true = parse_main(&format!("({}) true", not));
+    let not_false = parse_main(&format!("({}) false", not));
+    assert_eq!(reduce(not_true.clone()).to_string(), false_.to_string());
+    assert_eq!(reduce(not_false.clone()).to_string(), true_.to_string());
+    let not = parse_main(not);
+
+    // Several results in one net
+    let (code, ports) = Code::from_open_net(&[
+        OpenTerm {name: "a", expr: &not_true, holes: &[]},
+        OpenTerm {name: "b", expr: &not_false, holes: &[]},
+    ]).unwrap();
+    let names: Vec<_> = ports.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["a", "b"]);
+    let mut vm = VM::from_code(code);
+    assert_eq!(vm.interfaces().len(), 2);
+    vm.eval();
+    assert_eq!(vm.readback_interface(ports[0].1).unwrap().to_string(), false_.to_string());
+    assert_eq!(vm.readback_interface(ports[1].1).unwrap().to_string(), true_.to_string());
+
+    // A hole, filled after evaluating as far as possible without it
+    let (code, ports) = Code::from_open_net(&[OpenTerm {name: "r", expr: &not, holes: &["x"]}]).unwrap();
+    let mut vm = VM::from_code(code);
+    vm.eval();
+    assert_eq!(vm.readback_interface(ports[0].1).err(), Some(ReadbackError::Hole(ports[1].1)));
+    assert_eq!(vm.fill_hole(ports[0].1, Expr::leaf()), Err(ReadbackError::NotAHole(ports[0].1)));
+    vm.fill_hole(ports[1].1, false_.clone()).unwrap();
+    assert_eq!(vm.fill_hole(ports[1].1, Expr::leaf()), Err(ReadbackError::NotAnInterface(ports[1].1)));
+    assert_eq!(vm.interfaces(), [ports[0].1]);
+    vm.eval();
+    assert_eq!(vm.readback().to_string(), true_.to_string());
+
+    // Filled before evaluating
+    let (code, ports) = Code::from_open_net(&[OpenTerm {name: "r", expr: &not, holes: &["x"]}]).unwrap();
+    let mut vm = VM::from_code(code);
+    vm.fill_hole(ports[1].1, true_).unwrap();
+    vm.eval();
+    assert_eq!(vm.readback().to_string(), false_.to_string());
+
+    // Names of results and holes must all differ
+    let duplicate = |terms: &[OpenTerm]| Code::from_open_net(terms).err();
+    let term = |name, holes| OpenTerm {name, expr: &not, holes};
+    assert_eq!(duplicate(&[term("r", &[]), term("r", &[])]), Some(OpenNetError::DuplicateName("r".to_string())));
+    assert_eq!(duplicate(&[term("r", &["x", "x"])]), Some(OpenNetError::DuplicateName("x".to_string())));
+    assert_eq!(duplicate(&[term("r", &["s"]), term("s", &[])]), Some(OpenNetError::DuplicateName("s".to_string())));
+}
+
+#[test]
+fn test_session() {
+    // The program does some work before it takes its argument
+    let src = "not = t (t (t t) (t t t)) t\nmain = (\\a. \\b. \\x. [x, a, b]) (not true) (not (not false))";
+    let program = parse(src).unwrap();
+    let mut session = Session::new(program.clone()).unwrap();
+    let mut live_agents = Vec::new();
+    let mut interactions = Vec::new();
+    for arg in ["t", "t t", "t t t", "t (t t) t"] {
+        let arg = parse(&format!("main = {}", arg)).unwrap();
+        let mut app = program.clone();
+        app.children.push(arg.clone());
+        let before = session.vm().interaction_count();
+        assert_eq!(session.call(arg).unwrap().to_string(), reduce(app.clone()).to_string());
+        interactions.push(session.vm().interaction_count() - before);
+        live_agents.push(session.vm().live_agent_count());
+
+        let mut vm = VM::from_expr(app);
+        vm.eval();
+        assert!(*interactions.last().unwrap() < vm.interaction_count());
+    }
+    assert_eq!(session.call_count(), 4);
+    let vm = session.vm_mut();
+    assert_eq!(vm.apply_interface(usize::MAX, Expr::leaf()).err(), Some(ReadbackError::FreedAgent(usize::MAX)));
+    let agent = (0..).find(|a| vm.agent_type(*a).is_some_and(|t| t != AgentType::I)).unwrap();
+    assert_eq!(vm.erase_interface(agent).err(), Some(ReadbackError::NotAnInterface(agent)));
+    // The result of every call is freed
+    assert!(live_agents.windows(2).all(|w| w[0] == w[1]), "{:?}", live_agents);
+
+    let w = parse("main = \\x. x x").unwrap();
+    let mut session = Session::new(w.clone()).unwrap();
+    session.set_limits(1000, usize::MAX);
+    assert_eq!(session.call(w.clone()).err(), Some(CallError::Stopped(EvalStatus::OutOfFuel)));
+    // The failed call leaves nothing behind
+    assert_eq!(session.vm().active_pair_count(), 0);
+    assert_eq!(session.call(parse("main = t").unwrap()).unwrap().to_string(), "tt");
+    assert_eq!(session.call(w).err(), Some(CallError::Stopped(EvalStatus::OutOfFuel)));
+    assert_eq!(session.call(parse("main = t t").unwrap()).unwrap().to_string(), "tt(tt)");
+
+    // A net that is no longer connected to the program gives an error
+    let program = session.vm().interfaces()[0];
+    session.vm_mut().erase_interface(program).unwrap();
+    assert_eq!(session.call(Expr::leaf()).err(), Some(CallError::Readback(ReadbackError::FreedAgent(program))));
+}
+
+#[test]
+fn test_stats() {
+    let src = "not = t (t (t t) (t t t)) t\nmain = (\\f. [f true, f false]) not";
+    let mut vm = VM::from_expr(parse(src).unwrap());
+    vm.eval();
+    let stats = vm.stats();
+    assert_eq!(stats.interactions(), vm.interaction_count());
+    assert_eq!(stats.agents_allocated - stats.agents_freed, vm.live_agent_count() as u64);
+    assert!(stats.peak_live_agents >= vm.live_agent_count());
+    assert!(stats.peak_active_pairs >= 1);
+    // `f` is used twice
+    let f_d = RULES_NAME.iter().position(|name| *name == "RULE_F_D").unwrap();
+    assert!(stats.rule_counts[f_d] > 0);
+    assert_eq!(short_rule_name(f_d), "F-D");
+
+    let json = stats.to_json();
+    assert!(json.starts_with(&format!("{{\"interactions\": {}, \"rules\": {{\"L-E\": ", vm.interaction_count())));
+    assert!(stats.to_string().contains(&format!("\nTotal{:>27}\n", vm.interaction_count())));
+}
+
+// A writer whose output can be read while a tracer owns it
+#[derive(Clone, Default)]
+struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedBuffer {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[test]
+fn test_trace() {
+    let src = "t (t t t) t t";
+    let buffer = SharedBuffer::default();
+    let mut vm = VM::from_expr(parse(src).unwrap());
+    vm.set_tracer(Some(Tracer::new(buffer.clone(), TraceFormat::JsonLines)));
+    vm.eval();
+    vm.set_tracer(None).unwrap().finish().unwrap();
+    let trace = buffer.contents();
+    assert_eq!(trace.lines().count() as u64, vm.interaction_count());
+    let first = trace.lines().next().unwrap();
+    assert!(first.starts_with("{\"step\": 1, \"rule\": \"F-A\", \"left\": ["), "{}", first);
+    assert!(first.contains("\"created\": [[") && first.contains("\"Main\"]]]}"), "{}", first);
+
+    // Switched off after a few interactions
+    let src = "not = t (t (t t) (t t t)) t\nmain = (\\f. [f true, f false]) not";
+    let buffer = SharedBuffer::default();
+    let mut vm = VM::from_expr(parse(src).unwrap());
+    vm.set_tracer(Some(Tracer::new(buffer.clone(), TraceFormat::Chrome)));
+    vm.eval_with_limits(5, usize::MAX);
+    let tracer = vm.set_tracer(None).unwrap();
+    assert_eq!(tracer.interaction_count(), 5);
+    drop(tracer);
+    vm.eval();
+    let trace = buffer.contents();
+    assert!(trace.starts_with("[\n{\"name\": \"") && trace.ends_with("}}]\n"), "{}", trace);
+    assert_eq!(trace.matches("\"ph\": \"X\"").count(), 5);
+}
+
+#[test]
+fn test_to_dot() {
+    let mut vm = VM::from_expr(parse("t (t t t) t t").unwrap());
+    let dot = vm.to_dot();
+    assert!(dot.starts_with("graph net {\n") && dot.ends_with("}\n"));
+    // Every agent, and every wire once
+    assert_eq!(dot.matches("[label=").count(), vm.live_agent_count());
+    assert_eq!(dot.matches(" -- ").count(), 7);
+    assert!(dot.contains("  a1 [label=\"A #1\", style=filled, fillcolor=lightpink];\n"), "{}", dot);
+    assert!(dot.contains("  a1 -- a2 [taillabel=\"Main\", headlabel=\"Main\", dir=both, arrowtail=dot, arrowhead=dot, color=red, penwidth=2];\n"), "{}", dot);
+    assert!(dot.contains("  a0 -- a1 [taillabel=\"P0\", headlabel=\"P1\", dir=both, arrowtail=none, arrowhead=none];\n"), "{}", dot);
+
+    vm.eval();
+    let dot = vm.to_dot();
+    assert!(!dot.contains("red") && !dot.contains("lightpink"));
+    assert_eq!(dot.matches("[label=").count(), vm.live_agent_count());
+}
+
+#[test]
+fn test_step_back() {
+    let src = "not = t (t (t t) (t t t)) t\nmain = (\\f. [f true, f false]) not";
+    let mut vm = VM::from_expr(parse(src).unwrap());
+    vm.eval();
+    let expected = vm.readback().to_string();
+    let total = vm.interaction_count();
+
+    let mut vm = VM::from_expr(parse(src).unwrap());
+    vm.set_undo(true);
+    let snapshot = |vm: &VM| (vm.dump_heap(), vm.dump_active_pairs(), vm.dump_registers(), vm.stats().to_json());
+    let mut snapshots = vec![snapshot(&vm)];
+    while vm.step() == EvalState::EvalRunning {
+        snapshots.push(snapshot(&vm));
+    }
+    assert_eq!(vm.undo_depth() as u64, total);
+    // Every state is found again on the way back
+    while let Some(state) = snapshots.pop() {
+        if snapshots.len() < total as usize {
+            assert!(vm.step_back());
+        }
+        assert_eq!(snapshot(&vm), state);
+    }
+    assert!(!vm.step_back());
+    assert_eq!(vm.interaction_count(), 0);
+    vm.eval();
+    assert_eq!(vm.readback().to_string(), expected);
+
+    // Only the last steps are kept
+    let mut vm = VM::from_expr(parse(src).unwrap());
+    vm.set_undo(true);
+    vm.set_undo_limit(3);
+    vm.eval();
+    assert_eq!(vm.undo_depth(), 3);
+    while vm.step_back() {}
+    assert_eq!(vm.interaction_count(), total - 3);
+    vm.eval();
+    assert_eq!(vm.readback().to_string(), expected);
+}
+
+#[test]
+fn test_debugger() {
+    let src = "not = t (t (t t) (t t t)) t\nmain = (\\f. [f true, f false]) not";
+    let mut debugger = Debugger::new(VM::from_expr(parse(src).unwrap()));
+    let input = "break rule S-T\nbreak agent q\nbreak step 2\nc\n\n\nback 2\nwhere\ndelete 1\ndelete 1\nc\nq\nstep\n";
+    let mut out = Vec::new();
+    debugger.repl(input.as_bytes(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let stops: Vec<&str> = out.lines()
+        .filter_map(|line| line.rsplit("(tcdb) ").next()?.strip_prefix("Breakpoint: "))
+        .collect();
+    assert_eq!(stops, ["rule RULE_S_T", "step 2", "rule RULE_S_T", "step 2"], "{}", out);
+    assert_eq!(debugger.breakpoints(), [Breakpoint::Step(2)]);
+    // It stopped at `q`, at step 2, before an F agent meets an A agent
+    let location = out.strip_suffix("\n(tcdb) ").and_then(|out| out.lines().last()).unwrap();
+    assert!(location.starts_with("Step 2, ") && location.ends_with("  F-A"), "{}", out);
+    assert_eq!(debugger.vm.interaction_count(), 2);
+    let (left, right) = debugger.vm.next_pair().unwrap();
+    assert_eq!(debugger.vm.agent_type(left), Some(AgentType::F));
+    assert_eq!(debugger.vm.agent_type(right), Some(AgentType::A));
+    assert_eq!(Breakpoint::from_args("rule", "RULE_F_D"), Breakpoint::from_args("rule", "f-d"));
+    assert_eq!(Breakpoint::from_args("agent", "X"), None);
+
+    let interactions = debugger.vm.interaction_count();
+    assert!(debugger.command("back", &mut std::io::sink()).unwrap());
+    assert_eq!(debugger.vm.interaction_count(), interactions - 1);
+    while debugger.run(u64::MAX).is_some() {}
+    let mut vm = VM::from_expr(parse(src).unwrap());
+    vm.eval();
+    assert_eq!(debugger.vm.readback().to_string(), vm.readback().to_string());
+}
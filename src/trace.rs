@@ -0,0 +1,164 @@
+// A trace of every interaction of the VM, written as it runs. An interaction
+// is written with its rule, the two agents of the active pair, the agents it
+// created and the connections it made, in one of two formats:
+//   - JSON Lines, one object per interaction:
+//     {"step": 1, "rule": "F-A", "left": [3, "F"], "right": [7, "A"],
+//      "created": [[9, "T"]], "connected": [[[9, "P0"], [5, "Main"]], ...]}
+//   - The Chrome trace-event format, a JSON array of complete events, which
+//     trace viewers like Perfetto or chrome://tracing can open
+// Tracing is off unless a `Tracer` is given to the VM, and then it only costs a
+// check of an `Option` per interaction and per instruction
+
+use std::io::{self, Write};
+use std::time::Instant;
+
+use crate::agent::*;
+use crate::global::*;
+use crate::stats::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    JsonLines,
+    Chrome,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "jsonl" => Some(TraceFormat::JsonLines),
+            "chrome" => Some(TraceFormat::Chrome),
+            _ => None,
+        }
+    }
+}
+
+// The interaction being recorded
+struct Interaction {
+    step: u64,
+    rule: usize,
+    left: (HeapAddress, AgentType),
+    right: (HeapAddress, AgentType),
+    created: Vec<(HeapAddress, AgentType)>,
+    connected: Vec<((HeapAddress, PortNum), (HeapAddress, PortNum))>,
+    start: Option<Instant>,
+}
+
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    format: TraceFormat,
+    // When the tracer was made, which Chrome timestamps are relative to
+    epoch: Instant,
+    interaction: Interaction,
+    // Number of interactions written
+    count: u64,
+    finished: bool,
+    // The first write that failed. Tracing stops after it
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + Send + 'static, format: TraceFormat) -> Self {
+        Self {
+            out: Box::new(out),
+            format,
+            epoch: Instant::now(),
+            interaction: Interaction {
+                step: 0,
+                rule: 0,
+                left: (UNASSIGNED_PORT, AgentType::I),
+                right: (UNASSIGNED_PORT, AgentType::I),
+                created: Vec::new(),
+                connected: Vec::new(),
+                start: None,
+            },
+            count: 0,
+            finished: false,
+            error: None,
+        }
+    }
+
+    pub fn interaction_count(&self) -> u64 {
+        self.count
+    }
+
+    pub(crate) fn begin(&mut self, step: u64, rule: usize, left: (HeapAddress, AgentType), right: (HeapAddress, AgentType)) {
+        self.interaction.step = step;
+        self.interaction.rule = rule;
+        self.interaction.left = left;
+        self.interaction.right = right;
+        self.interaction.created.clear();
+        self.interaction.connected.clear();
+        self.interaction.start = Some(Instant::now());
+    }
+
+    pub(crate) fn created(&mut self, addr: HeapAddress, agent_type: AgentType) {
+        if self.interaction.start.is_some() {
+            self.interaction.created.push((addr, agent_type));
+        }
+    }
+
+    pub(crate) fn connected(&mut self, src: (HeapAddress, PortNum), dst: (HeapAddress, PortNum)) {
+        if self.interaction.start.is_some() {
+            self.interaction.connected.push((src, dst));
+        }
+    }
+
+    // Write the interaction started by `begin`
+    pub(crate) fn end(&mut self) {
+        let Some(start) = self.interaction.start.take() else {
+            return;
+        };
+        if self.error.is_some() {
+            return;
+        }
+        let interaction = &self.interaction;
+        let agent = |(addr, agent_type): (HeapAddress, AgentType)| format!("[{}, \"{:?}\"]", addr, agent_type);
+        let created: Vec<String> = interaction.created.iter().map(|a| agent(*a)).collect();
+        let connected: Vec<String> = interaction.connected.iter()
+            .map(|(src, dst)| format!("[[{}, \"{:?}\"], [{}, \"{:?}\"]]", src.0, src.1, dst.0, dst.1))
+            .collect();
+        let fields = format!("\"step\": {}, \"rule\": \"{}\", \"left\": {}, \"right\": {}, \"created\": [{}], \"connected\": [{}]",
+            interaction.step, short_rule_name(interaction.rule), agent(interaction.left), agent(interaction.right),
+            created.join(", "), connected.join(", "));
+        let result = match self.format {
+            TraceFormat::JsonLines => writeln!(self.out, "{{{}}}", fields),
+            TraceFormat::Chrome => {
+                let ts = start.duration_since(self.epoch).as_secs_f64() * 1e6;
+                let dur = start.elapsed().as_secs_f64() * 1e6;
+                let separator = if self.count == 0 { "[\n" } else { ",\n" };
+                write!(self.out, "{}{{\"name\": \"{}\", \"cat\": \"interaction\", \"ph\": \"X\", \"ts\": {:.3}, \"dur\": {:.3}, \
+                    \"pid\": 1, \"tid\": 1, \"args\": {{{}}}}}",
+                    separator, short_rule_name(interaction.rule), ts, dur, fields)
+            }
+        };
+        self.count += 1;
+        self.error = result.err();
+    }
+
+    // Close the trace and flush it. Dropping the tracer does the same, but
+    // ignores errors
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        if self.format == TraceFormat::Chrome {
+            let open = if self.count == 0 { "[" } else { "" };
+            writeln!(self.out, "{}]", open)?;
+        }
+        self.out.flush()
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
@@ -1,22 +1,147 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
 use crate::agent::*;
+use crate::arena::*;
 use crate::code::*;
+use crate::codec::*;
 use crate::expr::*;
 use crate::global::*;
 use crate::rules::*;
+use crate::stats::*;
+use crate::trace::*;
 use crate::containers::*;
 
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum EvalState {
     EvalRunning,
     EvalFinished,
+    // The cancellation token was triggered. The VM can still be inspected, and
+    // evaluation continues where it stopped if `step` is called again after
+    // the token is reset
+    EvalCancelled,
+}
+
+// Why `VM::eval_with_limits` returned
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EvalStatus {
+    Finished,
+    // The maximum number of interactions was reached
+    OutOfFuel,
+    // There were more live agents than allowed
+    OutOfMemory,
+    Cancelled,
+}
+
+// Stops an evaluation from another thread or a signal handler. Clones share the
+// same flag
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Given to the progress callback of the VM
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub interactions: u64,
+    pub live_agents: usize,
+    pub active_pairs: usize,
 }
 
-#[derive(Debug)]
+type ProgressCallback = Box<dyn FnMut(&Progress) + Send>;
+
+// Why the net could not be read back, or an interface could not be used
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadbackError {
+    // The net has no interface agent to start from
+    NoInterface,
+    // The agent at this address is not an interface
+    NotAnInterface(HeapAddress),
+    // The tree goes into the hole at this address, which is not filled yet
+    Hole(HeapAddress),
+    // A tree comes out of the interface at this address, it is not a hole
+    NotAHole(HeapAddress),
+    // This port of this agent is not connected to anything
+    Dangling(HeapAddress, PortNum),
+    // A port is connected to an agent that was freed
+    FreedAgent(HeapAddress),
+    // A tree is expected to come out of this port of this agent, but none does
+    UnexpectedPort(HeapAddress, AgentType, PortNum),
+}
+
+impl fmt::Display for ReadbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadbackError::NoInterface => write!(f, "no interface agent"),
+            ReadbackError::NotAnInterface(addr) => write!(f, "agent {} is not an interface", addr),
+            ReadbackError::Hole(addr) => write!(f, "the hole at {} is not filled", addr),
+            ReadbackError::NotAHole(addr) => write!(f, "interface {} is not a hole", addr),
+            ReadbackError::Dangling(addr, port_num) => {
+                write!(f, "port {:?} of agent {} is not connected", port_num, addr)
+            }
+            ReadbackError::FreedAgent(addr) => write!(f, "agent {} was freed", addr),
+            ReadbackError::UnexpectedPort(addr, agent_type, port_num) => {
+                write!(f, "port {:?} of agent {} ({:?}) is not an output", port_num, addr, agent_type)
+            }
+        }
+    }
+}
+
+// Return true if a tree comes out of the port `port_num` of an agent of type
+// `agent_type`, instead of going into it
+fn is_output(agent_type: AgentType, port_num: PortNum) -> bool {
+    matches!((agent_type, port_num),
+        (AgentType::L | AgentType::S | AgentType::F, PortNum::Main) |
+        (AgentType::A, PortNum::P1) |
+        (AgentType::D, PortNum::P0 | PortNum::P1) |
+        (AgentType::T, PortNum::P2) |
+        (AgentType::Q, PortNum::P3))
+}
+
+#[derive(Clone, Copy, Debug)]
 struct Equation {
     pub left_agent: HeapAddress,
     pub right_agent: HeapAddress,
 }
 
+// A change to the VM, with what is needed to revert it
+enum Undo {
+    // The agent was put at this address, when the heap had this size
+    Alloc(HeapAddress, usize),
+    Free(HeapAddress, Agent),
+    // The port held this before it was connected
+    Connect(HeapAddress, PortNum, Port),
+    PushPair,
+    PopPair(Equation),
+}
+
+// The changes made by a step, and the counters before it
+struct StepRecord {
+    changes: Vec<Undo>,
+    interactions: u64,
+    stats: Stats,
+    reg: [HeapAddress; MAX_AGENT_REG_SIZE as usize],
+}
+
 // Agents are stored in the heap, and everything else contains indices to
 // elements in the heap. The active pairs are pairs of agents connected by their
 // principal port
@@ -28,6 +153,25 @@ pub struct VM {
     // left agent max aux num (2) + Right agent max aux num (4) + most agents
     // created in a rule (4) = 10
     reg: [HeapAddress; MAX_AGENT_REG_SIZE as usize],
+
+    // The interface agents, which are the free ports of the net, in the order
+    // they were created, wherever they are in the heap. The result of a net
+    // made from a single tree is connected to the first one
+    interfaces: Vec<HeapAddress>,
+
+    // Number of rules applied so far
+    interactions: u64,
+
+    stats: Stats,
+    trace: Option<Tracer>,
+    // The steps that can be reverted, if they are recorded, oldest first
+    history: Option<VecDeque<StepRecord>>,
+    // The most steps kept in the history
+    undo_limit: usize,
+
+    cancel_token: Option<CancelToken>,
+    // Called every this many interactions
+    progress: Option<(u64, ProgressCallback)>,
 }
 
 impl VM {
@@ -37,6 +181,14 @@ impl VM {
             heap: Heap::new(),
             tape: Tape::from_code(code),
             reg: [const {UNASSIGNED_PORT}; MAX_AGENT_REG_SIZE as usize],
+            interfaces: Vec::new(),
+            interactions: 0,
+            stats: Stats::default(),
+            trace: None,
+            history: None,
+            undo_limit: usize::MAX,
+            cancel_token: None,
+            progress: None,
         };
         vm.exec();
         vm
@@ -48,28 +200,203 @@ impl VM {
         VM::from_code(code)
     }
 
+    // Replace the net by the net of `expr`. The counters, the cancellation
+    // token, the progress callback and the tracer are kept
+    pub fn reset(&mut self, expr: Expr) {
+        let VM {active_pairs, heap, tape, reg, interfaces, ..} = VM::from_expr(expr);
+        self.forget_history();
+        self.active_pairs = active_pairs;
+        self.heap = heap;
+        self.tape = tape;
+        self.reg = reg;
+        self.interfaces = interfaces;
+    }
+
     // Return true if there are no agents in the heap (only used for debugging)
     pub fn is_empty(&self) -> bool {
-        self.heap.len() == 0
+        self.heap.is_empty()
+    }
+
+    pub fn interaction_count(&self) -> u64 {
+        self.interactions
+    }
+
+    pub fn live_agent_count(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn active_pair_count(&self) -> usize {
+        self.active_pairs.size()
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    // Write every interaction to `tracer` from now on, or stop tracing if it is
+    // None. Return the tracer that was used until now
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.trace, tracer)
+    }
+
+    // Record the changes made by every step from now on, so that they can be
+    // reverted by `step_back`, or stop recording them and forget them
+    pub fn set_undo(&mut self, enabled: bool) {
+        self.history = enabled.then(VecDeque::new);
+    }
+
+    // Keep only the last `limit` steps in the history, at least one, and forget
+    // the older ones as new steps are recorded
+    pub fn set_undo_limit(&mut self, limit: usize) {
+        self.undo_limit = limit.max(1);
+        if let Some(history) = &mut self.history {
+            while history.len() > self.undo_limit {
+                history.pop_front();
+            }
+        }
+    }
+
+    // Return the number of steps that can be reverted
+    pub fn undo_depth(&self) -> usize {
+        self.history.as_ref().map_or(0, |history| history.len())
+    }
+
+    // Revert the last recorded step. Return false if there is none
+    pub fn step_back(&mut self) -> bool {
+        let Some(record) = self.history.as_mut().and_then(|history| history.pop_back()) else {
+            return false;
+        };
+        for change in record.changes.into_iter().rev() {
+            match change {
+                Undo::Alloc(addr, len) => {
+                    self.heap.remove(addr);
+                    self.heap.truncate(len);
+                }
+                Undo::Free(addr, agent) => self.heap.restore(addr, agent),
+                Undo::Connect(addr, port_num, port) => self.heap[addr].ports[port_num as usize] = port,
+                Undo::PushPair => {
+                    self.active_pairs.pop();
+                }
+                Undo::PopPair(eq) => self.active_pairs.push(eq),
+            }
+        }
+        self.interactions = record.interactions;
+        self.stats = record.stats;
+        self.reg = record.reg;
+        true
+    }
+
+    fn record(&mut self, change: Undo) {
+        if let Some(record) = self.history.as_mut().and_then(|history| history.back_mut()) {
+            record.changes.push(change);
+        }
+    }
+
+    // Changes made outside of a step cannot be reverted, and neither can the
+    // steps before them
+    fn forget_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    // Return the addresses of the next active pair `step` reduces
+    pub fn next_pair(&self) -> Option<(HeapAddress, HeapAddress)> {
+        let size = self.active_pairs.size();
+        (size > 0).then(|| {
+            let eq = &self.active_pairs[size - 1];
+            (eq.left_agent, eq.right_agent)
+        })
+    }
+
+    pub fn agent_type(&self, addr: HeapAddress) -> Option<AgentType> {
+        self.heap.get(addr).map(|agent| agent.agent_type)
+    }
+
+    fn alloc(&mut self, agent: Agent) -> HeapAddress {
+        let len = self.heap.full_len();
+        let addr = self.heap.push(agent);
+        if self.history.is_some() {
+            self.record(Undo::Alloc(addr, len));
+        }
+        self.stats.agents_allocated += 1;
+        self.stats.peak_live_agents = self.stats.peak_live_agents.max(self.heap.len());
+        addr
+    }
+
+    fn free(&mut self, addr: HeapAddress) {
+        if self.history.is_some() {
+            self.record(Undo::Free(addr, self.heap[addr].clone()));
+        }
+        self.heap.remove(addr);
+        self.stats.agents_freed += 1;
+    }
+
+    fn push_pair(&mut self, eq: Equation) {
+        if self.history.is_some() {
+            self.record(Undo::PushPair);
+        }
+        self.active_pairs.push(eq);
+        self.stats.peak_active_pairs = self.stats.peak_active_pairs.max(self.active_pairs.size());
+    }
+
+    // Make `step` stop when `token` is cancelled. It is only checked every
+    // `CANCEL_CHECK_INTERVAL` interactions, so that it costs nothing noticeable
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel_token = Some(token);
+    }
+
+    // Call `callback` every `interval` interactions
+    pub fn set_progress_callback(&mut self, interval: u64, callback: impl FnMut(&Progress) + Send + 'static) {
+        self.progress = Some((interval.max(1), Box::new(callback)));
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            interactions: self.interactions,
+            live_agents: self.live_agent_count(),
+            active_pairs: self.active_pair_count(),
+        }
     }
 
     // Execute an interaction rule. Pop the top of the stack until an equation
     // without names is reached. Then set up the registers for both agents, load
     // the code for the appropriate rule, and execute it
     pub fn step(&mut self) -> EvalState {
+        if self.interactions.is_multiple_of(CANCEL_CHECK_INTERVAL) &&
+            self.cancel_token.as_ref().is_some_and(|t| t.is_cancelled())
+        {
+            return EvalState::EvalCancelled;
+        }
+
         // Pop the next equation
         let eq = match self.active_pairs.pop() {
             None => return EvalState::EvalFinished,
             Some(x) => x,
         };
-        
-        crate::debug_log!("{}\n{}\n{}\n{:?}", self.get_reg(), self.get_heap(),
-            self.get_active_pairs(), eq);
+        if let Some(history) = &mut self.history {
+            if history.len() >= self.undo_limit {
+                history.pop_front();
+            }
+            history.push_back(StepRecord {
+                changes: vec![Undo::PopPair(eq)],
+                interactions: self.interactions,
+                stats: self.stats.clone(),
+                reg: self.reg,
+            });
+        }
 
+        // There is no rule for the interface: the tree connected to it by its
+        // main port is part of the result, and stays connected for readback.
+        // The other pairs still have to be reduced
         if self.heap[eq.left_agent].agent_type == AgentType::I ||
             self.heap[eq.right_agent].agent_type == AgentType::I
         {
-            return EvalState::EvalFinished
+            return if self.active_pairs.size() == 0 {
+                EvalState::EvalFinished
+            } else {
+                EvalState::EvalRunning
+            };
         }
 
         // Set up registers
@@ -85,17 +412,31 @@ impl VM {
         }
 
         // Find the appropriate rule and load its code
-        let left_agent_type = self.heap[eq.left_agent].agent_type as u8 - AgentType::L as u8;
-        let right_agent_type = self.heap[eq.right_agent].agent_type as u8 - AgentType::E as u8;
-        let code_index = left_agent_type * 5 + right_agent_type;
-        self.tape.set(Code::from_instrs(RULES[code_index as usize]));
-        crate::debug_log!("Invoking rule {}", RULES_NAME[code_index as usize]);
+        let code_index = rule_index(self.heap[eq.left_agent].agent_type, self.heap[eq.right_agent].agent_type)
+            .expect("There should be a rule for every active pair");
+        self.tape.set(Code::from_instrs(RULES[code_index]));
+        if let Some(tracer) = &mut self.trace {
+            tracer.begin(self.interactions + 1, code_index,
+                (eq.left_agent, self.heap[eq.left_agent].agent_type),
+                (eq.right_agent, self.heap[eq.right_agent].agent_type));
+        }
 
         // Execute the code
         self.exec();
+        if let Some(tracer) = &mut self.trace {
+            tracer.end();
+        }
+        self.interactions += 1;
+        self.stats.rule_counts[code_index] += 1;
+        if let Some((interval, _)) = &self.progress {
+            if self.interactions.is_multiple_of(*interval) {
+                let progress = self.progress();
+                (self.progress.as_mut().unwrap().1)(&progress);
+            }
+        }
 
-        self.heap.remove(eq.left_agent);
-        self.heap.remove(eq.right_agent);
+        self.free(eq.left_agent);
+        self.free(eq.right_agent);
 
         if self.active_pairs.size() == 0 {
             EvalState::EvalFinished
@@ -114,13 +455,20 @@ impl VM {
     }
 
     fn connect(&mut self, src_addr: HeapAddress, src_port: PortNum, dst_addr: HeapAddress, dst_port: PortNum) {
-        self.heap[src_addr as usize].ports[src_port as usize] =
+        if self.history.is_some() {
+            self.record(Undo::Connect(src_addr, src_port, self.heap[src_addr].ports[src_port as usize].clone()));
+            self.record(Undo::Connect(dst_addr, dst_port, self.heap[dst_addr].ports[dst_port as usize].clone()));
+        }
+        self.heap[src_addr].ports[src_port as usize] =
             Port::new(dst_addr, dst_port);
-        self.heap[dst_addr as usize].ports[dst_port as usize] =
+        self.heap[dst_addr].ports[dst_port as usize] =
             Port::new(src_addr, src_port);
+        if let Some(tracer) = &mut self.trace {
+            tracer.connected((src_addr, src_port), (dst_addr, dst_port));
+        }
         // If they are connected through their main ports, push them on the stack
         if src_port == PortNum::Main && dst_port == PortNum::Main {
-            self.active_pairs.push(Equation {
+            self.push_pair(Equation {
                 left_agent: src_addr,
                 right_agent: dst_addr
             });
@@ -129,11 +477,15 @@ impl VM {
 
     // Execute a single instruction
     fn exec_instr(&mut self, instr: Instr) {
-        crate::debug_log!("  > {:?}", instr);
         match instr {
             Instr::MkAgent(reg_addr, agent_type) => {
-                self.reg[reg_addr as usize] =
-                    self.heap.push(Agent::new(agent_type));
+                self.reg[reg_addr as usize] = self.alloc(Agent::new(agent_type));
+                if let Some(tracer) = &mut self.trace {
+                    tracer.created(self.reg[reg_addr as usize], agent_type);
+                }
+                if agent_type == AgentType::I {
+                    self.interfaces.push(self.reg[reg_addr as usize]);
+                }
             }
             Instr::Connect(src_addr, src_port, dst_addr, dst_port, mode) => {
                 let mut real_src_addr = self.reg[src_addr as usize];
@@ -157,86 +509,356 @@ impl VM {
             Instr::Load(reg_addr, heap_addr) => {
                 self.reg[reg_addr as usize] = heap_addr;
             }
-            Instr::Return => return,
+            Instr::Return => {}
+        }
+    }
+
+    // Evaluate the VM until there are no more active pairs present, or until
+    // it is cancelled
+    pub fn eval(&mut self) -> EvalStatus {
+        let start = Instant::now();
+        let mut state = self.step();
+        while state == EvalState::EvalRunning {
+            state = self.step();
+        }
+        self.stats.wall_time += start.elapsed();
+        if state == EvalState::EvalCancelled {
+            EvalStatus::Cancelled
+        } else {
+            EvalStatus::Finished
         }
     }
 
-    // Evaluate the VM until there are no more active pairs present
-    pub fn eval(&mut self) {
-        crate::debug_log!("\n=== Evaluating Code ===\n");
-        let mut step_count = 0;
-        while self.step() == EvalState::EvalRunning {
-            step_count += 1;
-            crate::debug_log!("\n[ Step {} ]\n", step_count);
+    // Evaluate the VM like `eval`, but stop after `max_interactions` rules, or
+    // as soon as there are more than `max_live_agents` agents in the heap. The
+    // VM is left in a consistent state, so evaluation can be continued by
+    // calling it again, with a fresh allowance of interactions
+    pub fn eval_with_limits(&mut self, max_interactions: u64, max_live_agents: usize) -> EvalStatus {
+        let start = Instant::now();
+        let status = self.run_with_limits(max_interactions, max_live_agents);
+        self.stats.wall_time += start.elapsed();
+        status
+    }
+
+    fn run_with_limits(&mut self, max_interactions: u64, max_live_agents: usize) -> EvalStatus {
+        let mut fuel = max_interactions;
+        loop {
+            if self.active_pairs.size() == 0 {
+                return EvalStatus::Finished;
+            }
+            if self.heap.len() > max_live_agents {
+                return EvalStatus::OutOfMemory;
+            }
+            if fuel == 0 {
+                return EvalStatus::OutOfFuel;
+            }
+            fuel -= 1;
+            match self.step() {
+                EvalState::EvalRunning => {}
+                EvalState::EvalFinished => return EvalStatus::Finished,
+                EvalState::EvalCancelled => return EvalStatus::Cancelled,
+            }
         }
-        crate::debug_log!("Step count: {}", step_count);
     }
 
-    // Get the register content of the VM as a String (only used for debugging)
-    fn get_reg(&self) -> String {
-        let mut str = format!("REG - {}:\n", self.reg.len());
-        let mut i = 0;
-        for e in &self.reg {
-            if *e != UNASSIGNED_PORT {
-                str.push_str(&format!("  {i}: {:?}\n", e));
-            } else {
-                str.push_str(&format!("  {i}: {:?}\n", e));
+    // Return the agents in the heap, one per line, with what their ports are
+    // connected to, like `3: F  P0 5.Main  P1 6.Main  Main 1.Main`
+    pub fn dump_heap(&self) -> String {
+        let mut str = format!("HEAP - {} / {}:\n", self.heap.len(), self.heap.full_len());
+        for (addr, agent) in self.heap.into_iter().enumerate() {
+            let Some(agent) = agent else { continue };
+            str.push_str(&format!("  {}: {:?}", addr, agent.agent_type));
+            for (i, port) in agent.ports.iter().enumerate() {
+                if port.agent_addr != UNASSIGNED_PORT {
+                    let port_num = PortNum::ALL[i];
+                    str.push_str(&format!("  {:?} {}.{:?}", port_num, port.agent_addr, port.port_num));
+                }
             }
-            i += 1;
+            str.push('\n');
         }
         str
     }
 
-    // Get the heap content of the VM as a String (only used for debugging)
-    fn get_heap(&self) -> String {
-        let mut str = format!("HEAP - {} / {}:\n", self.heap.len(), self.heap.full_len());
-        let mut i = 0;
-        for e in &self.heap {
-            str.push_str(&format!("  {i}: {:?}\n", e));
-            i += 1;
+    // Return the registers that hold an address, one per line
+    pub fn dump_registers(&self) -> String {
+        let mut str = format!("REG - {}:\n", self.reg.len());
+        for (i, addr) in self.reg.iter().enumerate().filter(|(_, addr)| **addr != UNASSIGNED_PORT) {
+            str.push_str(&format!("  {}: {}\n", i, addr));
         }
         str
     }
 
-    // Get the active pairs of the VM as a String (only used for debugging)
-    fn get_active_pairs(&self) -> String {
+    // Return the active pairs, one per line, from the next one to be reduced
+    pub fn dump_active_pairs(&self) -> String {
         let mut str = format!("ACTIVE PAIRS - {}:\n", self.active_pairs.size());
-        let mut i = 0;
-        for e in &self.active_pairs {
-            str.push_str(&format!(
-                "  {i}: {:?} ({:?} - {:?})\n",
-                e, self.heap[e.left_agent].agent_type, self.heap[e.right_agent].agent_type
-            ));
-            i += 1;
+        for eq in self.active_pairs.into_iter().rev() {
+            let left = self.heap[eq.left_agent].agent_type;
+            let right = self.heap[eq.right_agent].agent_type;
+            let rule = rule_index(left, right).map_or("no rule", |i| RULES_NAME[i]);
+            str.push_str(&format!("  {} ({:?}) >< {} ({:?})  {}\n", eq.left_agent, left, eq.right_agent, right, rule));
+        }
+        str
+    }
+
+    // Write the net as a Graphviz graph. Agents are labeled by type and heap
+    // address, and every wire is labeled at both ends by the ports it
+    // connects. Principal ports end in a dot, and active pairs are red
+    pub fn to_dot(&self) -> String {
+        let mut in_pairs = vec![false; self.heap.full_len()];
+        for eq in &self.active_pairs {
+            in_pairs[eq.left_agent] = true;
+            in_pairs[eq.right_agent] = true;
+        }
+        let mut str = String::from("graph net {\n  node [shape=circle, fontname=monospace];\n  edge [fontname=monospace, fontsize=10];\n");
+        for (addr, agent) in self.heap.into_iter().enumerate() {
+            let Some(agent) = agent else { continue };
+            let fill = if in_pairs[addr] { ", style=filled, fillcolor=lightpink" } else { "" };
+            str.push_str(&format!("  a{} [label=\"{:?} #{}\"{}];\n", addr, agent.agent_type, addr, fill));
+        }
+        for (addr, agent) in self.heap.into_iter().enumerate() {
+            let Some(agent) = agent else { continue };
+            for (i, port) in agent.ports.iter().enumerate() {
+                let port_num = PortNum::ALL[i];
+                // Every wire is written once, from its lower end
+                if port.agent_addr == UNASSIGNED_PORT || (port.agent_addr, port.port_num as usize) < (addr, i) {
+                    continue;
+                }
+                let marker = |port_num| if port_num == PortNum::Main { "dot" } else { "none" };
+                let active = port_num == PortNum::Main && port.port_num == PortNum::Main;
+                let style = if active { ", color=red, penwidth=2" } else { "" };
+                str.push_str(&format!("  a{} -- a{} [taillabel=\"{:?}\", headlabel=\"{:?}\", dir=both, arrowtail={}, arrowhead={}{}];\n",
+                    addr, port.agent_addr, port_num, port.port_num, marker(port_num), marker(port.port_num), style));
+            }
         }
+        str.push_str("}\n");
         str
     }
 
-    pub fn readback(&mut self) -> Expr {
-        // FIXME It's just a final readback, it assumes the topmost agent is at
-        // heap[0]. Since it doesn't handle names, duplication, application,
-        // etc., it cannot be used to read back an intermediate state of the VM
-        self.readback_agent(0)
+    // Read back the tree connected to the first interface. It can be called at
+    // any point during evaluation: rules that are not applied yet are read back
+    // as the applications they stand for, so reducing the result gives the
+    // same tree as finishing the evaluation. Panics if the net is broken
+    pub fn readback(&self) -> Expr {
+        self.try_readback().unwrap_or_else(|err| panic!("Readback failed: {}", err))
+    }
+
+    pub fn try_readback(&self) -> Result<Expr, ReadbackError> {
+        let interface = *self.interfaces.first().ok_or(ReadbackError::NoInterface)?;
+        self.readback_interface(interface)
+    }
+
+    // Return the heap addresses of the interface agents, in the order they were
+    // created
+    pub fn interfaces(&self) -> &[HeapAddress] {
+        &self.interfaces
+    }
+
+    // Read back the tree connected to the interface at `interface`, like
+    // `readback`
+    pub fn readback_interface(&self, interface: HeapAddress) -> Result<Expr, ReadbackError> {
+        let port_num = self.interface_port(interface)?;
+        let mut arena = Arena::new();
+        let root = self.readback_port(&mut arena, interface, port_num)?;
+        Ok(arena.to_expr(root))
+    }
+
+    // Return the port of the interface at `interface` that its tree is
+    // connected to
+    fn interface_port(&self, interface: HeapAddress) -> Result<PortNum, ReadbackError> {
+        let agent = self.agent(interface)?;
+        if agent.agent_type != AgentType::I {
+            return Err(ReadbackError::NotAnInterface(interface));
+        }
+        // It is P0, or the main port when the code made it an active pair
+        if agent.ports[PortNum::P0 as usize].agent_addr != UNASSIGNED_PORT {
+            Ok(PortNum::P0)
+        } else {
+            Ok(PortNum::Main)
+        }
+    }
+
+    // Apply the tree connected to `interface` to `arg`. The tree goes through
+    // a D agent: one copy stays connected to `interface`, and the other one is
+    // applied, so the tree can be applied again later. Return the interface
+    // that the result of the application is connected to
+    pub fn apply_interface(&mut self, interface: HeapAddress, arg: Expr) -> Result<HeapAddress, ReadbackError> {
+        let port_num = self.interface_port(interface)?;
+        let (root_addr, root_port) = self.peer(interface, port_num)?;
+        self.forget_history();
+        self.heap[interface].ports[port_num as usize] = Port::empty();
+        let (arg_addr, arg_port) = self.load_expr(arg);
+        let d = self.alloc(Agent::new(AgentType::D));
+        let a = self.alloc(Agent::new(AgentType::A));
+        let result = self.alloc(Agent::new(AgentType::I));
+        self.interfaces.push(result);
+        self.connect(d, PortNum::P0, interface, PortNum::P0);
+        self.connect(a, PortNum::Main, d, PortNum::P1);
+        self.connect(arg_addr, arg_port, a, PortNum::P0);
+        self.connect(a, PortNum::P1, result, PortNum::P0);
+        self.connect(root_addr, root_port, d, PortNum::Main);
+        Ok(result)
+    }
+
+    // Connect the tree of `interface` to an E agent, and remove the interface.
+    // The tree is freed as the VM evaluates
+    pub fn erase_interface(&mut self, interface: HeapAddress) -> Result<(), ReadbackError> {
+        let port_num = self.interface_port(interface)?;
+        let (root_addr, root_port) = self.peer(interface, port_num)?;
+        self.forget_history();
+        let e = self.alloc(Agent::new(AgentType::E));
+        self.interfaces.retain(|i| *i != interface);
+        self.free(interface);
+        self.connect(root_addr, root_port, e, PortNum::Main);
+        Ok(())
+    }
+
+    // Connect `expr` to the hole at `hole`, an interface that a tree goes into,
+    // and remove the hole. Evaluation continues with `step` or `eval`
+    pub fn fill_hole(&mut self, hole: HeapAddress, expr: Expr) -> Result<(), ReadbackError> {
+        let index = self.interfaces.iter().position(|i| *i == hole).ok_or(ReadbackError::NotAnInterface(hole))?;
+        let (addr, port_num) = self.peer(hole, PortNum::P0)?;
+        if is_output(self.agent(addr)?.agent_type, port_num) {
+            return Err(ReadbackError::NotAHole(hole));
+        }
+        self.forget_history();
+        let (root_addr, root_port) = self.load_expr(expr);
+        self.interfaces.remove(index);
+        self.free(hole);
+        self.connect(root_addr, root_port, addr, port_num);
+        Ok(())
+    }
+
+    // Add the net of `expr` to the heap, with its active pairs, and return the
+    // port its result comes out of, which is not connected yet
+    fn load_expr(&mut self, expr: Expr) -> (HeapAddress, PortNum) {
+        let net = VM::from_expr(expr);
+        let interface = net.interfaces[0];
+        let mut addrs = vec![UNASSIGNED_PORT; net.heap.full_len()];
+        for (i, agent) in net.heap.into_iter().enumerate() {
+            if let Some(agent) = agent {
+                if i != interface {
+                    addrs[i] = self.alloc(agent.clone());
+                }
+            }
+        }
+        for addr in addrs.iter().filter(|a| **a != UNASSIGNED_PORT) {
+            for port in self.heap[*addr].ports.iter_mut() {
+                if port.agent_addr != UNASSIGNED_PORT {
+                    port.agent_addr = addrs[port.agent_addr];
+                }
+            }
+        }
+        for eq in &net.active_pairs {
+            self.push_pair(Equation {
+                left_agent: addrs[eq.left_agent],
+                right_agent: addrs[eq.right_agent],
+            });
+        }
+        // The port connected to the interface now points to nothing
+        let root = &net.heap[interface].ports[PortNum::P0 as usize];
+        let root_addr = addrs[root.agent_addr];
+        self.heap[root_addr].ports[root.port_num as usize] = Port::empty();
+        (root_addr, root.port_num)
+    }
+
+    // Read back the result and decode it as a value of type `ty`. Fails if the
+    // result does not have the shape of that type
+    pub fn readback_value(&self, ty: &Type) -> Option<Value> {
+        Value::decode(&self.readback(), ty)
     }
 
-    fn readback_agent(&self, agent_addr: HeapAddress) -> Expr {
-        let agent = &self.heap[agent_addr];
-        match agent.agent_type {
-            AgentType::I => {
-                let new_addr = agent.ports[0].agent_addr;
-                if new_addr == UNASSIGNED_PORT {
-                    Expr { children: vec![] }
-                } else {
-                    self.readback_agent(new_addr)
+    // Return the port connected to the port `port_num` of the agent at `addr`
+    fn peer(&self, addr: HeapAddress, port_num: PortNum) -> Result<(HeapAddress, PortNum), ReadbackError> {
+        let port = &self.agent(addr)?.ports[port_num as usize];
+        if port.agent_addr == UNASSIGNED_PORT {
+            return Err(ReadbackError::Dangling(addr, port_num));
+        }
+        Ok((port.agent_addr, port.port_num))
+    }
+
+    fn agent(&self, addr: HeapAddress) -> Result<&Agent, ReadbackError> {
+        self.heap.get(addr).ok_or(ReadbackError::FreedAgent(addr))
+    }
+
+    // Read back the tree that flows into the port `port_num` of the agent at
+    // `addr`. Every agent port a tree comes out of is read as:
+    //   L, S(m), F(m, n) main port    t, t m, t m n
+    //   A(x, r) r, with f on main     f x
+    //   D(x, y) x or y, with m on main m, read once and shared by both copies
+    //   T(x, y, r) r, with m on main  t m x y, the application that made it
+    //   Q(x, y, z, r) r, with c on main t (t x y) z c
+    // It uses its own stack instead of recursion, so deep nets do not overflow
+    // the call stack
+    fn readback_port(&self, arena: &mut Arena, addr: HeapAddress, port_num: PortNum)
+        -> Result<NodeId, ReadbackError>
+    {
+        enum Task {
+            // Read the tree coming into this port
+            Visit(HeapAddress, PortNum),
+            // Pop a tree from the results and apply the tree below it to it
+            Apply,
+            // Replace the tree on top of the results by a leaf applied to it
+            Stem,
+            // Remember the tree on top of the results as the one duplicated by
+            // the D agent at this address
+            Share(HeapAddress),
+        }
+        let mut shared = std::collections::HashMap::new();
+        let mut tasks = vec![Task::Visit(addr, port_num)];
+        let mut results = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(addr, port_num) => {
+                    let (addr, port_num) = self.peer(addr, port_num)?;
+                    let agent_type = self.agent(addr)?.agent_type;
+                    match (agent_type, port_num) {
+                        (AgentType::L, PortNum::Main) => results.push(arena.leaf()),
+                        (AgentType::S, PortNum::Main) => {
+                            tasks.extend([Task::Stem, Task::Visit(addr, PortNum::P0)]);
+                        }
+                        (AgentType::F, PortNum::Main) => {
+                            tasks.extend([Task::Apply, Task::Visit(addr, PortNum::P1),
+                                Task::Stem, Task::Visit(addr, PortNum::P0)]);
+                        }
+                        (AgentType::A, PortNum::P1) => {
+                            tasks.extend([Task::Apply, Task::Visit(addr, PortNum::P0),
+                                Task::Visit(addr, PortNum::Main)]);
+                        }
+                        (AgentType::D, PortNum::P0 | PortNum::P1) => match shared.get(&addr) {
+                            Some(id) => results.push(*id),
+                            None => tasks.extend([Task::Share(addr), Task::Visit(addr, PortNum::Main)]),
+                        },
+                        (AgentType::T, PortNum::P2) => {
+                            tasks.extend([Task::Apply, Task::Visit(addr, PortNum::P1),
+                                Task::Apply, Task::Visit(addr, PortNum::P0),
+                                Task::Stem, Task::Visit(addr, PortNum::Main)]);
+                        }
+                        (AgentType::Q, PortNum::P3) => {
+                            tasks.extend([Task::Apply, Task::Visit(addr, PortNum::Main),
+                                Task::Apply, Task::Visit(addr, PortNum::P2),
+                                Task::Stem, Task::Apply, Task::Visit(addr, PortNum::P1),
+                                Task::Stem, Task::Visit(addr, PortNum::P0)]);
+                        }
+                        (AgentType::I, _) => return Err(ReadbackError::Hole(addr)),
+                        _ => return Err(ReadbackError::UnexpectedPort(addr, agent_type, port_num)),
+                    }
+                }
+                Task::Apply => {
+                    let x = results.pop().unwrap();
+                    let f = results.pop().unwrap();
+                    results.push(arena.app(f, x));
+                }
+                Task::Stem => {
+                    let x = results.pop().unwrap();
+                    let leaf = arena.leaf();
+                    results.push(arena.app(leaf, x));
+                }
+                Task::Share(addr) => {
+                    shared.insert(addr, *results.last().unwrap());
                 }
             }
-            AgentType::L => Expr::new(vec![]),
-            AgentType::S => Expr::new(vec![self.readback_agent(agent.ports[0].agent_addr)]),
-            AgentType::F => Expr::new(vec![
-                self.readback_agent(agent.ports[0].agent_addr),
-                self.readback_agent(agent.ports[1].agent_addr)]
-            ),
-            _ => Expr::new(vec![]),
         }
+        Ok(results.pop().unwrap())
     }
 }
+